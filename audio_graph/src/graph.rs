@@ -1,46 +1,229 @@
 //! # Audio graph
-use crate::module::Module;
+use crate::module::{Module, Port};
 use crate::sample::{Frame, Sample};
-use fixedbitset::FixedBitSet;
-use petgraph::algo::{toposort, DfsSpace};
+use petgraph::algo::tarjan_scc;
 use petgraph::prelude::*;
+use std::any::TypeId;
+use std::collections::HashMap;
+use std::fmt;
 
 pub type AudioNode = Box<dyn Module + Send>;
 
+/// Unit of work in the graph traversal.
+enum Step {
+    /// Node which is not a part of any cycle. It computes the whole block at once.
+    Block(NodeIndex),
+    /// Nodes forming a cycle, sorted by index. They compute the block frame by frame, because
+    /// delayed sources must provide their previous frame before sinks can compute the next one.
+    Frames(Vec<NodeIndex>),
+}
+
+/// Node connected to a port.
+#[derive(Clone, Copy)]
+struct Source {
+    node: NodeIndex,
+    /// Whether connection breaks a cycle and sink reads source's previous frame.
+    delayed: bool,
+}
+
 /// Structure which manages network of Modules.
 pub struct AudioGraph {
     /// Nodes are boxed Modules and edges represent source->sink connections.
-    graph: StableGraph<AudioNode, ()>,
-    /// `sample` writes output from source nodes into this buffer and then passes it to sink.
-    /// Buffer is reused during graph traversal and between samples to avoid memory allocations.
+    /// Edges are weighted with the sink's port source is connected to.
+    graph: StableGraph<AudioNode, Port>,
+    /// How many channels every node outputs.
+    channels: usize,
+    /// `process` writes output from source nodes into this buffer and then passes it to sink.
+    /// Buffer is reused during graph traversal and between blocks to avoid memory allocations.
+    /// It grows in `add_node` to fit `MAX_FRAMES` frames of input of the node with most inputs.
     input: Vec<Sample>,
     /// How many external inputs does graph expect.
     inputs: u8,
-    /// `sample` walks graph in topological order which is cached here.
-    order: Vec<NodeIndex>,
-    /// Output of the graph's last module.
+    /// `process` walks graph in topological order of its strongly connected components which is
+    /// cached here.
+    order: Vec<Step>,
+    /// Sources of each node indexed by `NodeIndex::index` and then by port, `None` for
+    /// unconnected ports. Cached together with `order` to avoid walking edges during `process`.
+    sources: Vec<Vec<Option<Source>>>,
+    /// Connections which close cycles and deliver source's output with one frame delay.
+    delayed: Vec<(NodeIndex, NodeIndex, Port)>,
+    /// Block of frames last computed by each node, indexed by `NodeIndex::index`.
+    buffers: Vec<Vec<Sample>>,
+    /// Output of the graph's output node.
     output: Vec<Sample>,
-    /// Workspace for topological sort is stored in structure for re-use.
-    space: DfsSpace<NodeIndex, FixedBitSet>,
+    /// Node set by `set_output`.
+    output_node: Option<NodeIndex>,
+    /// Pairs of (old, new) matching nodes planned by `plan_migration`.
+    migration: Option<Vec<(NodeIndex, NodeIndex)>>,
+}
+
+/// Index, type and number of inputs of each graph's node in the order they were added.
+///
+/// It's everything needed to match nodes of two graphs, so it allows to plan migration without
+/// access to the old graph, which might be owned by the audio thread already.
+#[derive(Clone, Default)]
+pub struct Layout(Vec<(NodeIndex, TypeId, u8)>);
+
+impl Layout {
+    /// Number of nodes.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Match nodes of the `old` layout to nodes of this one, as pairs of (old, new) indices.
+    ///
+    /// Nodes are compared in the order they were added to the graph, which is their stack
+    /// position for graphs built by `stack::build_graph`. Nodes match when they are of the same
+    /// type and have the same number of inputs, and the longest common subsequence of matching
+    /// nodes is picked, so that adding or removing nodes in the middle of the source doesn't
+    /// affect the match of the rest.
+    pub fn matches(&self, old: &Layout) -> Vec<(NodeIndex, NodeIndex)> {
+        let kind = |&(_, type_id, inputs): &(NodeIndex, TypeId, u8)| (type_id, inputs);
+        let xs = old.0.iter().map(kind).collect::<Vec<_>>();
+        let ys = self.0.iter().map(kind).collect::<Vec<_>>();
+        // Sources usually change in one place, so only the middle needs a search.
+        let prefix = xs.iter().zip(&ys).take_while(|(x, y)| x == y).count();
+        let suffix = xs[prefix..]
+            .iter()
+            .rev()
+            .zip(ys[prefix..].iter().rev())
+            .take_while(|(x, y)| x == y)
+            .count();
+        let mut pairs = (0..prefix).map(|i| (i, i)).collect::<Vec<_>>();
+        let (n, m) = (xs.len() - suffix, ys.len() - suffix);
+        lcs(&xs[prefix..n], &ys[prefix..m], (prefix, prefix), &mut pairs);
+        pairs.extend((0..suffix).map(|i| (n + i, m + i)));
+        pairs
+            .into_iter()
+            .map(|(i, j)| (old.0[i].0, self.0[j].0))
+            .collect()
+    }
 }
 
-/// Maximum number of sources to connect to sink.
-/// This number is required because input buffer is allocated during AudioGraph initialization and
-/// then re-used across all nodes sampling. There is no real need to have it hardcoded though.
-/// It can be made an argument of AudioGraph::new.
-const MAX_SOURCES: usize = 16;
+/// Push positions of the longest common subsequence of `xs` and `ys` into `pairs`, offset by
+/// `offset`. It's Hirschberg's algorithm, which takes linear space.
+fn lcs<T: PartialEq>(xs: &[T], ys: &[T], offset: (usize, usize), pairs: &mut Vec<(usize, usize)>) {
+    if xs.is_empty() || ys.is_empty() {
+        return;
+    }
+    if xs.len() == 1 {
+        if let Some(j) = ys.iter().position(|y| *y == xs[0]) {
+            pairs.push((offset.0, offset.1 + j));
+        }
+        return;
+    }
+    let mid = xs.len() / 2;
+    let head = lcs_lengths(xs[..mid].iter(), ys.iter());
+    let tail = lcs_lengths(xs[mid..].iter().rev(), ys.iter().rev());
+    // Split `ys` where subsequences of both halves of `xs` are the longest in total.
+    let split = (0..=ys.len())
+        .max_by_key(|&j| (head[j] + tail[ys.len() - j], std::cmp::Reverse(j)))
+        .unwrap_or(0);
+    lcs(&xs[..mid], &ys[..split], offset, pairs);
+    lcs(
+        &xs[mid..],
+        &ys[split..],
+        (offset.0 + mid, offset.1 + split),
+        pairs,
+    );
+}
+
+/// Lengths of the longest common subsequences of `xs` and each prefix of `ys`.
+fn lcs_lengths<'a, T: PartialEq + 'a>(
+    xs: impl Iterator<Item = &'a T>,
+    ys: impl Iterator<Item = &'a T> + Clone,
+) -> Vec<u32> {
+    let m = ys.clone().count();
+    let mut row = vec![0; m + 1];
+    let mut previous = vec![0; m + 1];
+    for x in xs {
+        std::mem::swap(&mut row, &mut previous);
+        for (j, y) in ys.clone().enumerate() {
+            row[j + 1] = if x == y {
+                previous[j] + 1
+            } else {
+                previous[j + 1].max(row[j])
+            };
+        }
+    }
+    row
+}
+
+/// Invalid wiring detected by AudioGraph connection methods.
+///
+/// Note that cycles are not errors: connections closing them are delayed by one frame, ref
+/// `AudioGraph::delayed_edges`.
+#[derive(Debug)]
+pub enum GraphError {
+    /// Node with the given index is not in the graph.
+    NodeNotFound(NodeIndex),
+    /// Node does not have a port with the given index.
+    PortOutOfRange(NodeIndex, Port),
+    /// Number of sources passed to `set_sources` doesn't match node's `inputs`.
+    WrongSourcesCount {
+        node: NodeIndex,
+        expected: u8,
+        actual: usize,
+    },
+    /// Node's port has no source connected.
+    UnconnectedPort(NodeIndex, Port),
+}
+
+impl fmt::Display for GraphError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GraphError::NodeNotFound(node) => write!(f, "node {} is not found", node.index()),
+            GraphError::PortOutOfRange(node, port) => {
+                write!(f, "node {} has no port {}", node.index(), port.0)
+            }
+            GraphError::WrongSourcesCount {
+                node,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "node {} expects {} sources, but {} were given",
+                node.index(),
+                expected,
+                actual
+            ),
+            GraphError::UnconnectedPort(node, port) => {
+                write!(
+                    f,
+                    "port {} of node {} is not connected",
+                    port.0,
+                    node.index()
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for GraphError {}
+
+/// Maximum number of frames each node computes at once.
+/// Longer blocks passed to `process` are split into chunks of this size. It
+/// determines the size of buffers allocated in advance.
+pub const MAX_FRAMES: usize = 512;
 
 impl AudioGraph {
     pub fn new(channels: u8, inputs: u8) -> Self {
-        let graph = StableGraph::default();
-        let space = DfsSpace::new(&graph);
         AudioGraph {
-            graph,
-            input: vec![0.0; MAX_SOURCES * channels as usize],
+            graph: StableGraph::default(),
+            channels: channels as _,
+            input: Vec::new(),
             inputs,
             order: Vec::new(),
+            sources: Vec::new(),
+            delayed: Vec::new(),
+            buffers: Vec::new(),
             output: vec![0.0; channels as _],
-            space,
+            output_node: None,
+            migration: None,
         }
     }
 
@@ -51,60 +234,380 @@ impl AudioGraph {
     /// Add node to the graph and return index assigned to the node.
     /// This index is stable and could be used to reference the node when building connections.
     pub fn add_node(&mut self, n: AudioNode) -> NodeIndex {
-        self.graph.add_node(n)
+        let input_size = n.inputs() as usize * MAX_FRAMES * self.channels;
+        if self.input.len() < input_size {
+            self.input.resize(input_size, 0.0);
+        }
+        let idx = self.graph.add_node(n);
+        if self.buffers.len() <= idx.index() {
+            let size = MAX_FRAMES * self.channels;
+            self.buffers
+                .resize_with(idx.index() + 1, || vec![0.0; size]);
+            self.sources.resize_with(idx.index() + 1, Vec::new);
+        }
+        idx
+    }
+
+    /// Find `node`'s input port by its name.
+    pub fn port(&self, node: NodeIndex, name: &str) -> Option<Port> {
+        self.graph[node]
+            .ports()
+            .iter()
+            .position(|&port| port == name)
+            .map(|i| Port(i as _))
     }
 
     /// Connect nodes in a chain, from left to right.
+    /// Each node is connected to the first port of the next one.
     /// It clears nodes' sources before connecting, except for the first one.
-    pub fn chain(&mut self, nodes: &[NodeIndex]) {
+    pub fn chain(&mut self, nodes: &[NodeIndex]) -> Result<(), GraphError> {
+        for pair in nodes.windows(2) {
+            self.check_node(pair[0])?;
+            self.check_port(pair[1], Port(0))?;
+        }
         for pair in nodes.windows(2) {
             let source = pair[0];
             let sink = pair[1];
             self.clear_sources(sink);
-            self.graph.update_edge(source, sink, ());
+            self.graph.add_edge(source, sink, Port(0));
         }
         self.update_order();
+        Ok(())
     }
 
-    /// Set node `a` as a single source of node `b`.
-    /// It clears `b`'s sources before connecting, to set multiple sources use `set_sources`.
-    pub fn connect(&mut self, a: NodeIndex, b: NodeIndex) {
-        self.clear_sources(b);
-        self.graph.update_edge(a, b, ());
+    /// Set node `a` as a source of node `b` connected to the given `port`.
+    /// Source previously connected to the same port is disconnected, other ports are untouched.
+    pub fn connect(&mut self, a: NodeIndex, b: NodeIndex, port: Port) -> Result<(), GraphError> {
+        self.check_node(a)?;
+        self.check_port(b, port)?;
+        self.disconnect(b, port);
+        self.graph.add_edge(a, b, port);
         self.update_order();
+        Ok(())
     }
 
     /// Set multiple sources for the `sink` node.
     /// It clears `sink`'s sources before connecting.
-    /// `source`s' outputs are layouted in `sink` input buffer in the provided order.
+    /// `sources` are connected to ports in the provided order, i.e. their outputs are layouted in
+    /// `sink` input buffer in the provided order. Number of sources must match `sink`'s `inputs`.
     /// Ref `Module::sample` doc for an example of input layout.
-    pub fn set_sources(&mut self, sink: NodeIndex, sources: &[NodeIndex]) {
-        self.clear_sources(sink);
-        // ref `sample` method comments for the reason of reversing sources
-        for source in sources.iter().rev() {
-            self.graph.update_edge(*source, sink, ());
-        }
+    pub fn set_sources(
+        &mut self,
+        sink: NodeIndex,
+        sources: &[NodeIndex],
+    ) -> Result<(), GraphError> {
+        self.connect_sources(sink, sources)?;
         self.update_order();
+        Ok(())
     }
 
-    pub fn set_sources_rev(&mut self, sink: NodeIndex, sources: &[NodeIndex]) {
+    /// Same as `set_sources`, but traversal order is left for `update_order` (or `optimize`) to
+    /// update, so that building a graph node by node doesn't reorder it after every node.
+    pub(crate) fn connect_sources(
+        &mut self,
+        sink: NodeIndex,
+        sources: &[NodeIndex],
+    ) -> Result<(), GraphError> {
+        let inputs = self.check_node(sink)?.inputs();
+        if sources.len() != inputs as usize {
+            return Err(GraphError::WrongSourcesCount {
+                node: sink,
+                expected: inputs,
+                actual: sources.len(),
+            });
+        }
+        for (i, source) in sources.iter().enumerate() {
+            self.check_node(*source)?;
+            self.check_port(sink, Port(i as _))?;
+        }
         self.clear_sources(sink);
-        // ref `sample` method comments for the reason of reversing sources
-        for source in sources.iter() {
-            self.graph.update_edge(*source, sink, ());
+        for (i, source) in sources.iter().enumerate() {
+            self.graph.add_edge(*source, sink, Port(i as _));
+        }
+        Ok(())
+    }
+
+    /// Check that every port of every node has a source connected.
+    pub fn validate(&self) -> Result<(), GraphError> {
+        for idx in self.graph.node_indices() {
+            for port in (0..self.graph[idx].inputs()).map(Port) {
+                if !self
+                    .graph
+                    .edges_directed(idx, Incoming)
+                    .any(|edge| *edge.weight() == port)
+                {
+                    return Err(GraphError::UnconnectedPort(idx, port));
+                }
+            }
         }
+        Ok(())
+    }
+
+    /// Make `node`'s output the output of the graph.
+    ///
+    /// If it's not set the graph's output is the output of the last node in traversal order,
+    /// which is ambiguous when the graph has several nodes without sinks.
+    pub fn set_output(&mut self, node: NodeIndex) -> Result<(), GraphError> {
+        self.check_node(node)?;
+        self.output_node = Some(node);
+        Ok(())
+    }
+
+    /// Optimize the graph without changing its output:
+    /// - pure nodes (ref `Module::is_pure`) which sources are constant are replaced with
+    ///   constants, so that e.g. `440 2 *` is computed once rather than every frame;
+    /// - nodes of the same type with the same key (ref `Module::key`) and sources are merged into
+    ///   one;
+    /// - nodes which don't contribute to the output set by `set_output` are removed.
+    ///
+    /// Node indices of the remaining nodes are kept. Traversal order is updated before and after,
+    /// so connections don't have to be followed by `update_order`.
+    pub fn optimize(&mut self) {
+        self.update_order();
+        self.fold_constants();
+        self.merge_duplicates();
+        self.remove_dead_nodes();
         self.update_order();
     }
 
+    /// Connections which were delayed by one frame to break cycles, as (source, sink, port).
+    /// Updated by `update_order`.
+    pub fn delayed_edges(&self) -> &[(NodeIndex, NodeIndex, Port)] {
+        &self.delayed
+    }
+
     pub fn clear(&mut self) {
         self.order.clear();
+        self.sources.clear();
+        self.delayed.clear();
+        self.buffers.clear();
+        self.output_node = None;
         self.graph.clear();
     }
 
     /// Update node traversal order.
     /// It must be called after any connection change.
+    ///
+    /// Cycles are allowed. Nodes of each cycle are ordered by their indices, i.e. in the order
+    /// they were added to the graph, and connections going against that order are delayed by one
+    /// frame. Ref `delayed_edges` to find out which connections were delayed.
     pub fn update_order(&mut self) {
-        self.order = toposort(&self.graph, Some(&mut self.space)).unwrap_or_else(|_| vec![]);
+        self.order.clear();
+        self.delayed.clear();
+        // tarjan_scc returns components in reverse topological order.
+        for mut component in tarjan_scc(&self.graph).into_iter().rev() {
+            let idx = component[0];
+            if component.len() == 1 && self.graph.find_edge(idx, idx).is_none() {
+                self.order.push(Step::Block(idx));
+            } else {
+                component.sort();
+                for &sink in &component {
+                    for edge in self.graph.edges_directed(sink, Incoming) {
+                        let source = edge.source();
+                        if source >= sink && component.binary_search(&source).is_ok() {
+                            self.delayed.push((source, sink, *edge.weight()));
+                        }
+                    }
+                }
+                self.order.push(Step::Frames(component));
+            }
+        }
+        for idx in self.graph.node_indices() {
+            // Edges are weighted with the port they are connected to, which defines the source
+            // position in the input layout regardless of the order edges were added in.
+            // Ref `Module::sample` doc for an example of input layout.
+            let sources = &mut self.sources[idx.index()];
+            sources.clear();
+            sources.resize(self.graph[idx].inputs() as _, None);
+            for edge in self.graph.edges_directed(idx, Incoming) {
+                if let Some(source) = sources.get_mut(edge.weight().0 as usize) {
+                    let node = edge.source();
+                    let delayed = self.delayed.contains(&(node, idx, *edge.weight()));
+                    *source = Some(Source { node, delayed });
+                }
+            }
+        }
+    }
+
+    /// Kinds of the graph's nodes in the order they were added, ref `Layout::matches`.
+    pub fn layout(&self) -> Layout {
+        Layout(
+            self.graph
+                .node_indices()
+                .map(|idx| {
+                    let node = &self.graph[idx];
+                    (idx, node.as_ref().as_any().type_id(), node.inputs())
+                })
+                .collect(),
+        )
+    }
+
+    /// Match nodes of the graph with the `old` layout to nodes of this one and keep the result
+    /// for `migrate`.
+    ///
+    /// Matching allocates, so it's better to plan migration in advance, outside of the audio
+    /// thread, and let `migrate` just copy the state.
+    pub fn plan_migration(&mut self, old: &Layout) {
+        self.migration = Some(self.layout().matches(old));
+    }
+
+    /// The node which output is the output of the graph.
+    fn output_node(&self) -> Option<NodeIndex> {
+        if self.output_node.is_some() {
+            return self.output_node;
+        }
+        match self.order.last() {
+            Some(Step::Block(idx)) => Some(*idx),
+            Some(Step::Frames(nodes)) => nodes.last().cloned(),
+            None => None,
+        }
+    }
+
+    /// Whether the node outputs the same frame all the time.
+    fn is_constant(&self, node: NodeIndex) -> bool {
+        let node = &self.graph[node];
+        node.is_pure() && node.inputs() == 0
+    }
+
+    /// Replace pure nodes with constant sources with constants, in traversal order, so that
+    /// chains of them are folded at once.
+    fn fold_constants(&mut self) {
+        let channels = self.channels;
+        let mut input = Vec::new();
+        // Cycles are never constant.
+        let blocks = self
+            .order
+            .iter()
+            .filter_map(|step| match step {
+                Step::Block(idx) => Some(*idx),
+                Step::Frames(_) => None,
+            })
+            .collect::<Vec<_>>();
+        for idx in blocks {
+            let node = &self.graph[idx];
+            let inputs = node.inputs() as usize;
+            if !node.is_pure() || inputs == 0 {
+                continue;
+            }
+            let sources = &self.sources[idx.index()];
+            if !sources
+                .iter()
+                .all(|source| source.is_some_and(|s| self.is_constant(s.node)))
+            {
+                continue;
+            }
+            input.clear();
+            input.resize(inputs * channels, 0.0);
+            for (port, source) in sources.iter().enumerate() {
+                let source = source.map(|s| self.graph[s.node].output());
+                layout(&mut input, port, inputs, source);
+            }
+            let node = &mut self.graph[idx];
+            node.sample(&input);
+            let output = node.output().to_vec();
+            self.graph[idx] = Box::new(Folded(output));
+            self.clear_sources(idx);
+            // Sources of the following nodes are looked up in `self.sources`.
+            self.sources[idx.index()].clear();
+        }
+    }
+
+    /// Merge nodes which compute the same output, in traversal order, so that sinks of merged
+    /// nodes could be merged too.
+    fn merge_duplicates(&mut self) {
+        let mut seen = HashMap::new();
+        // Nodes in cycles have state.
+        let blocks = self
+            .order
+            .iter()
+            .filter_map(|step| match step {
+                Step::Block(idx) => Some(*idx),
+                Step::Frames(_) => None,
+            })
+            .collect::<Vec<_>>();
+        for idx in blocks {
+            let node = &self.graph[idx];
+            let key = match node.key() {
+                Some(key) => key,
+                None => continue,
+            };
+            let sources = self.sources[idx.index()]
+                .iter()
+                .map(|source| source.map(|s| s.node))
+                .collect::<Vec<_>>();
+            let kind = (node.as_ref().as_any().type_id(), key, sources);
+            let original = match seen.get(&kind) {
+                Some(&original) => original,
+                None => {
+                    seen.insert(kind, idx);
+                    continue;
+                }
+            };
+            let sinks = self
+                .graph
+                .edges_directed(idx, Outgoing)
+                .map(|edge| (edge.target(), *edge.weight()))
+                .collect::<Vec<_>>();
+            for (sink, port) in sinks {
+                self.graph.add_edge(original, sink, port);
+                // Sources of the following nodes are looked up in `self.sources`.
+                if let Some(Some(source)) = self.sources[sink.index()].get_mut(port.0 as usize) {
+                    source.node = original;
+                }
+            }
+            if self.output_node == Some(idx) {
+                self.output_node = Some(original);
+            }
+            self.graph.remove_node(idx);
+        }
+    }
+
+    /// Remove nodes the output node doesn't depend on, if it's set.
+    fn remove_dead_nodes(&mut self) {
+        let output = match self.output_node {
+            Some(output) => output,
+            None => return,
+        };
+        let mut alive = vec![false; self.buffers.len()];
+        let mut stack = vec![output];
+        while let Some(idx) = stack.pop() {
+            if !alive[idx.index()] {
+                alive[idx.index()] = true;
+                stack.extend(self.graph.neighbors_directed(idx, Incoming));
+            }
+        }
+        for (i, alive) in alive.into_iter().enumerate() {
+            if !alive {
+                self.graph.remove_node(NodeIndex::new(i));
+            }
+        }
+    }
+
+    fn check_node(&self, node: NodeIndex) -> Result<&AudioNode, GraphError> {
+        self.graph
+            .node_weight(node)
+            .ok_or(GraphError::NodeNotFound(node))
+    }
+
+    /// Check that `node` has the `port`.
+    fn check_port(&self, node: NodeIndex, port: Port) -> Result<(), GraphError> {
+        if port.0 >= self.check_node(node)?.inputs() {
+            Err(GraphError::PortOutOfRange(node, port))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Remove connection to the `sink`'s `port`, if any.
+    fn disconnect(&mut self, sink: NodeIndex, port: Port) {
+        let edge = self
+            .graph
+            .edges_directed(sink, Incoming)
+            .find(|edge| *edge.weight() == port)
+            .map(|edge| edge.id());
+        if let Some(edge) = edge {
+            self.graph.remove_edge(edge);
+        }
     }
 
     /// Remove all incoming connections of the node.
@@ -118,6 +621,125 @@ impl AudioGraph {
             self.graph.remove_edge(edge);
         }
     }
+
+    /// Compute `frames` (which must not exceed `MAX_FRAMES`) frames of every node.
+    ///
+    /// Nodes outside of cycles process the whole block at once, so that graph traversal and
+    /// dynamic dispatch cost is paid once per block rather than once per frame.
+    fn render(&mut self, input: &[Sample], frames: usize) {
+        let channels = self.channels;
+        let graph = &mut self.graph;
+        let buffers = &mut self.buffers;
+        let scratch = &mut self.input;
+        for step in &self.order {
+            match step {
+                Step::Block(idx) => {
+                    let idx = *idx;
+                    let node_inputs = graph[idx].inputs() as usize;
+                    let size = node_inputs * channels;
+                    let node_input = if node_inputs > 0 {
+                        for (port, source) in self.sources[idx.index()].iter().enumerate() {
+                            let source_buffer = source.map(|s| &buffers[s.node.index()]);
+                            for (frame, input) in
+                                scratch[..frames * size].chunks_mut(size).enumerate()
+                            {
+                                let source_frame = source_buffer.map(|buffer| {
+                                    &buffer[frame * channels..(frame + 1) * channels]
+                                });
+                                layout(input, port, node_inputs, source_frame);
+                            }
+                        }
+                        &scratch[..frames * size]
+                    } else {
+                        // If node does not have any inputs it might be waiting for external input.
+                        input
+                    };
+                    let buffer = &mut buffers[idx.index()][..frames * channels];
+                    graph[idx].process(node_input, buffer, frames);
+                }
+                Step::Frames(nodes) => {
+                    let input_size = input.len().checked_div(frames).unwrap_or(0);
+                    for frame in 0..frames {
+                        let range = frame * channels..(frame + 1) * channels;
+                        for &idx in nodes {
+                            let node_inputs = graph[idx].inputs() as usize;
+                            let size = node_inputs * channels;
+                            let node_input = if node_inputs > 0 {
+                                for (port, source) in self.sources[idx.index()].iter().enumerate() {
+                                    let source_frame = source.map(|s| {
+                                        if s.delayed {
+                                            // Source is computed after the sink in the cycle, its
+                                            // current output is still the previous frame.
+                                            graph[s.node].output()
+                                        } else {
+                                            &buffers[s.node.index()][range.clone()]
+                                        }
+                                    });
+                                    layout(&mut scratch[..size], port, node_inputs, source_frame);
+                                }
+                                &scratch[..size]
+                            } else {
+                                &input[frame * input_size..(frame + 1) * input_size]
+                            };
+                            graph[idx].sample(node_input);
+                            buffers[idx.index()][range.clone()]
+                                .copy_from_slice(graph[idx].output());
+                        }
+                    }
+                }
+            }
+        }
+        if let Some(node) = self.output_node() {
+            self.output.copy_from_slice(self.graph[node].output());
+        }
+    }
+}
+
+/// Output of a pure subgraph of constants, computed by `AudioGraph::optimize`.
+struct Folded(Vec<Sample>);
+
+impl Module for Folded {
+    fn inputs(&self) -> u8 {
+        0
+    }
+
+    fn output(&self) -> &Frame {
+        &self.0
+    }
+
+    fn sample(&mut self, _input: &Frame) {}
+
+    fn process(&mut self, _input: &[Sample], output: &mut [Sample], _frames: usize) {
+        for frame in output.chunks_mut(self.0.len()) {
+            frame.copy_from_slice(&self.0);
+        }
+    }
+
+    fn is_pure(&self) -> bool {
+        true
+    }
+
+    fn key(&self) -> Option<Vec<u64>> {
+        Some(self.0.iter().map(|x| x.to_bits()).collect())
+    }
+}
+
+/// Put `source` frame into `input` frame of the node with `inputs` ports at the given `port`.
+/// Unconnected port (`None` source) receives silence.
+/// Ref `Module::sample` doc for an example of input layout.
+fn layout(input: &mut [Sample], port: usize, inputs: usize, source: Option<&Frame>) {
+    match source {
+        Some(source) => {
+            for (channel, sample) in source.iter().enumerate() {
+                input[port + channel * inputs] = *sample;
+            }
+        }
+        None => {
+            for x in input.iter_mut().skip(port).step_by(inputs) {
+                *x = 0.0;
+            }
+        }
+    }
 }
 
 impl Module for AudioGraph {
@@ -130,31 +752,447 @@ impl Module for AudioGraph {
     }
 
     fn sample(&mut self, input: &Frame) {
-        for idx in self.order.iter().map(|x| *x) {
-            let graph = &mut self.graph;
-            let node_inputs = graph[idx].inputs() as usize;
-            if node_inputs > 0 {
-                // NOTE neighbors_directed walks edges starting from the most recently added (is it
-                // guaranteed?). This is the reason why connection methods (connect, set_sources,
-                // chain etc.) call clear_sources first and reverse sources. Always resetting
-                // sources instead of finer-grained manipulation reduces risk of confusing their
-                // order. We might want to consider to name edges and pass HashMap instead instead
-                // of Vec as input. But it implies non-neglegible performance hit.
-                //
-                // Ref `Module::sample` doc for an example of input layout.
-                for (i, source) in graph.neighbors_directed(idx, Incoming).enumerate() {
-                    for (channel, sample) in graph[source].output().iter().enumerate() {
-                        self.input[i + channel * node_inputs] = *sample;
-                    }
+        self.render(input, 1);
+    }
+
+    /// Migrate state of each node of the `old` graph to the matching node of this one, ref
+    /// `Layout::matches` for the matching rules.
+    ///
+    /// Uses matches from `plan_migration` if any, otherwise computes them.
+    fn migrate(&mut self, old: &dyn Module) {
+        if let Some(old) = old.as_any().downcast_ref::<AudioGraph>() {
+            let computed;
+            let migration = match &self.migration {
+                Some(migration) => migration,
+                None => {
+                    computed = self.layout().matches(&old.layout());
+                    &computed
                 }
-                graph[idx].sample(&self.input);
-            } else {
-                // If node does not have any inputs it might be waiting for external input.
-                graph[idx].sample(input);
+            };
+            for &(x, y) in migration {
+                // Plan might be made for another graph, skip nodes which are not there.
+                if let (Some(old_node), Some(node)) =
+                    (old.graph.node_weight(x), self.graph.node_weight_mut(y))
+                {
+                    node.migrate(old_node.as_ref());
+                }
+            }
+            for (x, y) in self.output.iter_mut().zip(&old.output) {
+                *x = *y;
             }
         }
-        if let Some(&node) = self.order.last() {
-            self.output.copy_from_slice(self.graph[node].output());
+    }
+
+    /// Total migration size of nodes matched by `plan_migration`, 0 if it wasn't called.
+    fn migration_size(&self) -> usize {
+        let migration = match &self.migration {
+            Some(migration) => migration,
+            None => return 0,
+        };
+        migration
+            .iter()
+            .filter_map(|&(_, y)| self.graph.node_weight(y))
+            .map(|node| node.migration_size())
+            .sum()
+    }
+
+    /// Migrate parts of nodes matched by `plan_migration`, in the order of the plan.
+    fn migrate_part(&mut self, old: &dyn Module, start: usize, end: usize) {
+        let (old, migration) = match (old.as_any().downcast_ref::<AudioGraph>(), &self.migration) {
+            (Some(old), Some(migration)) => (old, migration),
+            _ => return,
+        };
+        let mut offset = 0;
+        for &(x, y) in migration {
+            if offset >= end {
+                break;
+            }
+            let node = match self.graph.node_weight_mut(y) {
+                Some(node) => node,
+                None => continue,
+            };
+            let size = node.migration_size();
+            let (a, b) = (start.max(offset), end.min(offset + size));
+            // Plan might be made for another graph, skip nodes which are not there.
+            if let (true, Some(old_node)) = (a < b, old.graph.node_weight(x)) {
+                node.migrate_part(old_node.as_ref(), a - offset, b - offset);
+            }
+            offset += size;
+        }
+    }
+
+    fn reset(&mut self) {
+        // Every node has a buffer, so its length is the bound of node indices.
+        for i in 0..self.buffers.len() {
+            if let Some(node) = self.graph.node_weight_mut(NodeIndex::new(i)) {
+                node.reset();
+            }
+        }
+        for buffer in self.buffers.iter_mut() {
+            buffer.iter_mut().for_each(|x| *x = 0.0);
+        }
+        self.output.iter_mut().for_each(|x| *x = 0.0);
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: u32) {
+        for i in 0..self.buffers.len() {
+            if let Some(node) = self.graph.node_weight_mut(NodeIndex::new(i)) {
+                node.set_sample_rate(sample_rate);
+            }
+        }
+    }
+
+    fn process(&mut self, input: &[Sample], output: &mut [Sample], frames: usize) {
+        if frames == 0 {
+            return;
+        }
+        let input_size = input.len() / frames;
+        let output_size = output.len() / frames;
+        for (i, output) in output.chunks_mut(MAX_FRAMES * output_size).enumerate() {
+            let n = output.len() / output_size;
+            let start = i * MAX_FRAMES * input_size;
+            self.render(&input[start..start + n * input_size], n);
+            match self.output_node() {
+                Some(node) => output.copy_from_slice(&self.buffers[node.index()][..output.len()]),
+                None => output.iter_mut().for_each(|x| *x = 0.0),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Outputs the same value all the time.
+    struct Const(Vec<Sample>);
+
+    impl Module for Const {
+        fn inputs(&self) -> u8 {
+            0
         }
+
+        fn output(&self) -> &Frame {
+            &self.0
+        }
+
+        fn sample(&mut self, _input: &Frame) {}
+
+        fn is_pure(&self) -> bool {
+            true
+        }
+
+        fn key(&self) -> Option<Vec<u64>> {
+            Some(self.0.iter().map(|x| x.to_bits()).collect())
+        }
+    }
+
+    /// Multiplies its two inputs.
+    struct Mul(Vec<Sample>);
+
+    impl Module for Mul {
+        fn inputs(&self) -> u8 {
+            2
+        }
+
+        fn output(&self) -> &Frame {
+            &self.0
+        }
+
+        fn sample(&mut self, input: &Frame) {
+            for (output, input) in self.0.iter_mut().zip(input.chunks(2)) {
+                *output = input[0] * input[1];
+            }
+        }
+
+        fn is_pure(&self) -> bool {
+            true
+        }
+
+        fn key(&self) -> Option<Vec<u64>> {
+            Some(Vec::new())
+        }
+    }
+
+    /// Phase accumulator, which outputs its phase and advances it by the input.
+    struct Phasor(Vec<Sample>);
+
+    impl Module for Phasor {
+        fn inputs(&self) -> u8 {
+            1
+        }
+
+        fn output(&self) -> &Frame {
+            &self.0
+        }
+
+        fn sample(&mut self, input: &Frame) {
+            for (phase, dx) in self.0.iter_mut().zip(input) {
+                *phase = (*phase + dx).fract();
+            }
+        }
+
+        fn migrate(&mut self, old: &dyn Module) {
+            if let Some(old) = old.as_any().downcast_ref::<Self>() {
+                self.0.copy_from_slice(&old.0);
+            }
+        }
+    }
+
+    fn constant(graph: &mut AudioGraph, x: Sample) -> NodeIndex {
+        graph.add_node(Box::new(Const(vec![x])))
+    }
+
+    fn phasor(graph: &mut AudioGraph, freq: NodeIndex) -> NodeIndex {
+        let idx = graph.add_node(Box::new(Phasor(vec![0.0])));
+        graph.set_sources(idx, &[freq]).unwrap();
+        idx
+    }
+
+    fn mul(graph: &mut AudioGraph, a: NodeIndex, b: NodeIndex) -> NodeIndex {
+        let idx = graph.add_node(Box::new(Mul(vec![0.0])));
+        graph.set_sources(idx, &[a, b]).unwrap();
+        idx
+    }
+
+    fn render(graph: &mut AudioGraph, frames: usize) -> Vec<Sample> {
+        let mut output = vec![0.0; frames];
+        graph.process(&[], &mut output, frames);
+        output
+    }
+
+    /// Build the graph with `build`, optimize it and check that it outputs the same samples as the
+    /// unoptimized one.
+    fn optimized(build: impl Fn(&mut AudioGraph)) -> AudioGraph {
+        let mut graph = AudioGraph::new(1, 0);
+        build(&mut graph);
+        let mut optimized = AudioGraph::new(1, 0);
+        build(&mut optimized);
+        optimized.optimize();
+        assert_eq!(render(&mut optimized, 100), render(&mut graph, 100));
+        optimized
+    }
+
+    #[test]
+    fn optimize_folds_constants() {
+        // 0.01 2 * phasor
+        let graph = optimized(|graph| {
+            let a = constant(graph, 0.01);
+            let b = constant(graph, 2.0);
+            let freq = mul(graph, a, b);
+            let out = phasor(graph, freq);
+            graph.set_output(out).unwrap();
+        });
+        let freq = NodeIndex::new(2);
+        assert_eq!(graph.node(freq).inputs(), 0);
+        assert_eq!(graph.node(freq).output(), &[0.02]);
+        assert_eq!(graph.layout().len(), 2);
+    }
+
+    #[test]
+    fn optimize_merges_identical_chains() {
+        // 0.01 phasor =p p 2 * p 2 * *
+        let graph = optimized(|graph| {
+            let freq = constant(graph, 0.01);
+            let p = phasor(graph, freq);
+            let two = constant(graph, 2.0);
+            let a = mul(graph, p, two);
+            let two = constant(graph, 2.0);
+            let b = mul(graph, p, two);
+            let out = mul(graph, a, b);
+            graph.set_output(out).unwrap();
+        });
+        assert_eq!(graph.layout().len(), 5);
+    }
+
+    #[test]
+    fn optimize_keeps_identical_stateful_nodes() {
+        // 0.01 phasor 0.01 phasor *
+        let graph = optimized(|graph| {
+            let a = constant(graph, 0.01);
+            let a = phasor(graph, a);
+            let b = constant(graph, 0.01);
+            let b = phasor(graph, b);
+            let out = mul(graph, a, b);
+            graph.set_output(out).unwrap();
+        });
+        // Constants are merged, phasors are not.
+        assert_eq!(graph.layout().len(), 4);
+    }
+
+    #[test]
+    fn optimize_removes_dropped_chains() {
+        // 0.01 phasor pop 0.03 phasor
+        let graph = optimized(|graph| {
+            let a = constant(graph, 0.01);
+            phasor(graph, a);
+            let b = constant(graph, 0.03);
+            let out = phasor(graph, b);
+            graph.set_output(out).unwrap();
+        });
+        let nodes = graph.layout().0;
+        assert_eq!(nodes.len(), 2);
+        assert!(nodes.iter().all(|(idx, _, _)| idx.index() >= 2));
+    }
+
+    /// Phasor starting at 0.5 and advancing by the half of its own phase, followed by `0.5 *`.
+    /// Returns the phasor and the node feeding it back.
+    fn feedback(graph: &mut AudioGraph) -> (NodeIndex, NodeIndex) {
+        let half = constant(graph, 0.5);
+        let phasor = graph.add_node(Box::new(Phasor(vec![0.5])));
+        let fed = mul(graph, half, phasor);
+        graph.connect(fed, phasor, Port(0)).unwrap();
+        let out = mul(graph, fed, half);
+        graph.set_output(out).unwrap();
+        (phasor, fed)
+    }
+
+    #[test]
+    fn feedback_is_delayed_by_one_frame() {
+        let mut graph = AudioGraph::new(1, 0);
+        let (phasor, fed) = feedback(&mut graph);
+        assert_eq!(graph.delayed_edges(), &[(fed, phasor, Port(0))]);
+        let output = render(&mut graph, 100);
+        let mut phase: Sample = 0.5;
+        let mut previous = 0.0;
+        for x in output {
+            phase = (phase + previous).fract();
+            previous = 0.5 * phase;
+            assert_eq!(x, 0.5 * previous);
+        }
+    }
+
+    #[test]
+    fn feedback_renders_the_same_in_blocks_and_frames() {
+        let mut graph = AudioGraph::new(1, 0);
+        feedback(&mut graph);
+        let blocks = render(&mut graph, 100);
+        let mut graph = AudioGraph::new(1, 0);
+        feedback(&mut graph);
+        let frames = (0..100)
+            .map(|_| {
+                graph.sample(&[]);
+                graph.output()[0]
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(blocks, frames);
+    }
+
+    #[test]
+    fn port_out_of_range() {
+        let mut graph = AudioGraph::new(1, 0);
+        let a = constant(&mut graph, 1.0);
+        let b = mul(&mut graph, a, a);
+        let error = graph.connect(a, b, Port(2)).unwrap_err();
+        assert!(matches!(error, GraphError::PortOutOfRange(node, Port(2)) if node == b));
+    }
+
+    #[test]
+    fn wrong_sources_count() {
+        let mut graph = AudioGraph::new(1, 0);
+        let a = constant(&mut graph, 1.0);
+        let b = graph.add_node(Box::new(Mul(vec![0.0])));
+        let error = graph.set_sources(b, &[a]).unwrap_err();
+        assert!(matches!(
+            error,
+            GraphError::WrongSourcesCount { node, expected: 2, actual: 1 } if node == b
+        ));
+    }
+
+    #[test]
+    fn unconnected_port() {
+        let mut graph = AudioGraph::new(1, 0);
+        let a = constant(&mut graph, 1.0);
+        let b = graph.add_node(Box::new(Mul(vec![0.0])));
+        graph.connect(a, b, Port(0)).unwrap();
+        let error = graph.validate().unwrap_err();
+        assert!(matches!(error, GraphError::UnconnectedPort(node, Port(1)) if node == b));
+        graph.connect(a, b, Port(1)).unwrap();
+        assert!(graph.validate().is_ok());
+    }
+
+    /// Layout of nodes of `kinds`, which are numbers of inputs of nodes of the same type.
+    fn layout(kinds: &[u8]) -> Layout {
+        let kinds = kinds.iter().enumerate();
+        Layout(
+            kinds
+                .map(|(i, &inputs)| (NodeIndex::new(i), TypeId::of::<Const>(), inputs))
+                .collect(),
+        )
+    }
+
+    /// Length of the longest common subsequence computed with the full table.
+    fn lcs_length(xs: &[u8], ys: &[u8]) -> usize {
+        let mut table = vec![vec![0; ys.len() + 1]; xs.len() + 1];
+        for (i, x) in xs.iter().enumerate() {
+            for (j, y) in ys.iter().enumerate() {
+                table[i + 1][j + 1] = if x == y {
+                    table[i][j] + 1
+                } else {
+                    table[i][j + 1].max(table[i + 1][j])
+                };
+            }
+        }
+        table[xs.len()][ys.len()]
+    }
+
+    #[test]
+    fn layout_matches_longest_common_subsequence() {
+        // Linear congruential generator is enough to make up layouts.
+        let mut seed = 1u32;
+        let mut kinds = |n: usize| {
+            (0..n)
+                .map(|_| {
+                    seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                    (seed >> 29) as u8
+                })
+                .collect::<Vec<_>>()
+        };
+        for &(n, m) in &[(0, 5), (1, 1), (7, 3), (20, 25), (64, 50)] {
+            let (xs, ys) = (kinds(n), kinds(m));
+            let pairs = layout(&ys).matches(&layout(&xs));
+            assert_eq!(pairs.len(), lcs_length(&xs, &ys));
+            for pair in pairs.windows(2) {
+                assert!(pair[0].0 < pair[1].0 && pair[0].1 < pair[1].1);
+            }
+            for &(x, y) in &pairs {
+                assert_eq!(xs[x.index()], ys[y.index()]);
+            }
+        }
+    }
+
+    #[test]
+    fn layout_matches_large_layouts() {
+        let old = (0..10_000).map(|i| (i % 7) as u8).collect::<Vec<_>>();
+        let mut new = old.clone();
+        new.insert(4_000, 9);
+        new.remove(6_000);
+        let pairs = layout(&new).matches(&layout(&old));
+        assert_eq!(pairs.len(), old.len() - 1);
+    }
+
+    #[test]
+    fn migration_keeps_state_of_nodes_around_inserted_one() {
+        let mut old = AudioGraph::new(1, 0);
+        let f1 = constant(&mut old, 0.01);
+        let a = phasor(&mut old, f1);
+        let f2 = constant(&mut old, 0.03);
+        let b = phasor(&mut old, f2);
+        old.set_output(b).unwrap();
+        render(&mut old, 10);
+
+        let mut new = AudioGraph::new(1, 0);
+        let f1 = constant(&mut new, 0.01);
+        let new_a = phasor(&mut new, f1);
+        let inserted = mul(&mut new, new_a, f1);
+        let f2 = constant(&mut new, 0.03);
+        let new_b = phasor(&mut new, f2);
+        new.set_output(new_b).unwrap();
+        new.migrate(&old);
+
+        assert_eq!(new.node(new_a).output(), old.node(a).output());
+        assert_eq!(new.node(new_b).output(), old.node(b).output());
+        assert_eq!(new.node(inserted).output(), &[0.0]);
+        assert_eq!(render(&mut new, 4), render(&mut old, 4));
     }
 }
@@ -0,0 +1,129 @@
+//! # Handoff
+//!
+//! Audio thread must not wait for locks and must not allocate or deallocate memory, otherwise it
+//! might miss its deadline and produce a glitch. Handoff delivers new graphs to the audio thread
+//! and brings graphs it has retired back for deallocation through a pair of fixed-size lock-free
+//! queues.
+use crate::graph::AudioGraph;
+use crossbeam_queue::ArrayQueue;
+use std::sync::Arc;
+
+/// Create connected ends of the handoff.
+///
+/// `capacity` is how many graphs could wait in each direction. Queues are allocated here and
+/// never grow.
+pub fn handoff(capacity: usize) -> (GraphSender, GraphReceiver) {
+    let graphs = Arc::new(ArrayQueue::new(capacity));
+    let garbage = Arc::new(ArrayQueue::new(capacity));
+    (
+        GraphSender {
+            graphs: graphs.clone(),
+            garbage: garbage.clone(),
+        },
+        GraphReceiver { graphs, garbage },
+    )
+}
+
+/// End of the handoff which lives on a non-realtime thread.
+pub struct GraphSender {
+    graphs: Arc<ArrayQueue<AudioGraph>>,
+    garbage: Arc<ArrayQueue<AudioGraph>>,
+}
+
+impl GraphSender {
+    /// Send graph to the audio thread.
+    /// Graph is returned back if the audio thread didn't pick up previous ones yet.
+    #[allow(clippy::result_large_err)]
+    pub fn send(&self, graph: AudioGraph) -> Result<(), AudioGraph> {
+        self.graphs.push(graph)
+    }
+
+    /// Deallocate graphs retired by the audio thread and return how many were there.
+    /// Call it periodically to keep the garbage queue from filling up.
+    pub fn collect(&self) -> usize {
+        let mut count = 0;
+        while let Some(graph) = self.garbage.pop() {
+            drop(graph);
+            count += 1;
+        }
+        count
+    }
+}
+
+/// End of the handoff which lives on the audio thread. None of its methods block or allocate.
+pub struct GraphReceiver {
+    graphs: Arc<ArrayQueue<AudioGraph>>,
+    garbage: Arc<ArrayQueue<AudioGraph>>,
+}
+
+impl GraphReceiver {
+    /// Take the latest graph sent, if any.
+    /// Graphs sent before it are outdated, so they are sent back for deallocation, ref `retire`.
+    pub fn receive(&self) -> Option<AudioGraph> {
+        let mut latest = self.graphs.pop()?;
+        while let Some(graph) = self.graphs.pop() {
+            self.retire(std::mem::replace(&mut latest, graph));
+        }
+        Some(latest)
+    }
+
+    /// Send graph which is done playing back for deallocation.
+    ///
+    /// If the garbage queue is full, because the sender doesn't collect it or is gone already,
+    /// graph is dropped right here as the last resort.
+    pub fn retire(&self, graph: AudioGraph) {
+        if let Err(graph) = self.garbage.push(graph) {
+            drop(graph);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::module::Module;
+
+    /// Empty graph which is told apart from others by its number of inputs.
+    fn graph(id: u8) -> AudioGraph {
+        AudioGraph::new(1, id)
+    }
+
+    #[test]
+    fn send_fails_when_queue_is_full() {
+        let (sender, receiver) = handoff(2);
+        assert!(sender.send(graph(1)).is_ok());
+        assert!(sender.send(graph(2)).is_ok());
+        let rejected = sender.send(graph(3)).unwrap_err();
+        assert_eq!(rejected.inputs(), 3);
+        assert_eq!(receiver.receive().map(|graph| graph.inputs()), Some(2));
+        assert!(sender.send(graph(3)).is_ok());
+    }
+
+    #[test]
+    fn receive_returns_latest_graph() {
+        let (sender, receiver) = handoff(4);
+        assert!(receiver.receive().is_none());
+        for id in 1..=3 {
+            assert!(sender.send(graph(id)).is_ok());
+        }
+        assert_eq!(receiver.receive().map(|graph| graph.inputs()), Some(3));
+        assert!(receiver.receive().is_none());
+        // Skipped graphs are sent back for deallocation.
+        assert_eq!(sender.collect(), 2);
+    }
+
+    #[test]
+    fn retired_graphs_are_collected() {
+        let (sender, receiver) = handoff(2);
+        assert!(sender.send(graph(1)).is_ok());
+        let old = receiver.receive().unwrap();
+        receiver.retire(old);
+        assert_eq!(sender.collect(), 1);
+        assert_eq!(sender.collect(), 0);
+        // Graphs which don't fit into the garbage queue are dropped right away.
+        for id in 1..=3 {
+            receiver.retire(graph(id));
+        }
+        assert_eq!(sender.collect(), 2);
+    }
+}
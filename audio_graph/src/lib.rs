@@ -4,10 +4,14 @@
 //! audio signal `Module`s.
 
 mod graph;
+mod handoff;
 mod module;
 mod sample;
 pub mod stack;
+mod swapper;
 
-pub use crate::graph::{AudioGraph, AudioNode};
-pub use crate::module::Module;
+pub use crate::graph::{AudioGraph, AudioNode, GraphError, Layout, MAX_FRAMES};
+pub use crate::handoff::{handoff, GraphReceiver, GraphSender};
+pub use crate::module::{AsAny, Module, Port};
+pub use crate::swapper::GraphSwapper;
 pub use sample::{Frame, Sample};
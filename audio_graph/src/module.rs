@@ -1,11 +1,42 @@
 //! # Module
 use crate::sample::{Frame, Sample};
+use std::any::Any;
+
+/// Index of Module's input port, i.e. position of a source in Module's input layout.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Port(pub u8);
+
+impl From<u8> for Port {
+    fn from(index: u8) -> Self {
+        Port(index)
+    }
+}
+
+/// Upcast to `Any`, which allows to recover the concrete type of `dyn Module`.
+/// It is implemented for every `'static` type, ref `Module::migrate` for the use case.
+pub trait AsAny: Any {
+    fn as_any(&self) -> &dyn Any;
+}
+
+impl<T: Any> AsAny for T {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
 
 /// Defines behavior of sound-producing node.
-pub trait Module {
+pub trait Module: AsAny {
     /// How many sources must be connected to this module.
     fn inputs(&self) -> u8;
 
+    /// Names of the input ports in the order of input layout.
+    ///
+    /// Modules with variable number of inputs might name only some of them (or none), the rest of
+    /// ports are addressable by index only.
+    fn ports(&self) -> &'static [&'static str] {
+        &[]
+    }
+
     /// Get Module's current frame.
     ///
     /// Must contain the same value if no `sample` was called for Module in-between.
@@ -17,7 +48,7 @@ pub trait Module {
     ///
     /// This is example of how multi-channel multi-source outputs are collected into the
     /// input buffer (stereo audio and 3 incoming connections case):
-    /// ```
+    /// ```text
     /// first source output: [ 0 1 ]
     ///                        | |
     ///                        | +------------+
@@ -33,4 +64,86 @@ pub trait Module {
     ///                                |chan0|chan1|
     /// ```
     fn sample(&mut self, input: &[Sample]);
+
+    /// Compute the next `frames` frames at once.
+    ///
+    /// `input` contains `frames` input frames one after another, each of them laid out the same
+    /// way as `sample` expects. `output` must be filled with `frames` output frames one after
+    /// another. After the call `output()` must return the last computed frame.
+    ///
+    /// Default implementation calls `sample` for every frame. Modules which can do better when
+    /// they see the whole block at once should override it.
+    fn process(&mut self, input: &[Sample], output: &mut [Sample], frames: usize) {
+        if frames == 0 {
+            return;
+        }
+        let input_size = input.len() / frames;
+        let output_size = output.len() / frames;
+        for (i, output) in output.chunks_mut(output_size).enumerate() {
+            self.sample(&input[i * input_size..(i + 1) * input_size]);
+            output.copy_from_slice(self.output());
+        }
+    }
+
+    /// Take over the internal state (phases, delay lines, filter memory etc.) of the `old`
+    /// module which is being replaced by this one, e.g. when source code is reloaded.
+    ///
+    /// `old` might be of a different type, so implementations are expected to downcast it with
+    /// `old.as_any().downcast_ref::<Self>()` and do nothing if it fails. `old` is left intact,
+    /// which allows to keep running it for a while, e.g. to crossfade.
+    ///
+    /// Default implementation does nothing, which is right for stateless modules.
+    fn migrate(&mut self, _old: &dyn Module) {}
+
+    /// How many samples of state `migrate` copies, if it's too much to copy at once on the
+    /// audio thread, e.g. long delay lines. Such state is copied in parts with `migrate_part`
+    /// before `migrate` is called, ref `GraphSwapper::swap`.
+    ///
+    /// Default implementation returns 0, which is right for modules with small state.
+    fn migration_size(&self) -> usize {
+        0
+    }
+
+    /// Copy samples `start..end` of the state counted by `migration_size` from the `old` module.
+    ///
+    /// `old` keeps running between calls, parts are copied in order and followed by `migrate`,
+    /// which then only has to catch up with what `old` did since the first part.
+    ///
+    /// Default implementation does nothing.
+    fn migrate_part(&mut self, _old: &dyn Module, _start: usize, _end: usize) {}
+
+    /// Return to the initial state, as if the module was just created: zero phases, silent delay
+    /// lines, cleared filter memory etc.
+    ///
+    /// Default implementation does nothing, which is right for stateless modules.
+    fn reset(&mut self) {}
+
+    /// Adapt to the new sample rate, e.g. when the host changes it.
+    ///
+    /// Modules bake values derived from the sample rate in at construction, so they must be
+    /// recomputed here. Internal state is kept as long as it makes sense in the new rate. It might
+    /// allocate (e.g. to resize delay lines), so don't call it on the audio thread.
+    ///
+    /// Default implementation does nothing, which is right for modules not depending on the
+    /// sample rate.
+    fn set_sample_rate(&mut self, _sample_rate: u32) {}
+
+    /// Whether the output depends on the current input frame only: the module has no state, it
+    /// doesn't read external input and doesn't depend on the sample rate.
+    ///
+    /// Pure modules which sources are constant are computed once when graph is optimized, ref
+    /// `AudioGraph::optimize`.
+    fn is_pure(&self) -> bool {
+        false
+    }
+
+    /// Parameters of a stateless module which, together with its type and sources, determine its
+    /// output, e.g. the function of `Fn1` or the value of `Constant`.
+    ///
+    /// Modules of the same type with the same key and sources are merged into one when graph is
+    /// optimized, ref `AudioGraph::optimize`. Default implementation returns `None`, which keeps
+    /// the module out of merging and is the only right choice for modules with state.
+    fn key(&self) -> Option<Vec<u64>> {
+        None
+    }
 }
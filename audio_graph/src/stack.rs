@@ -1,56 +1,707 @@
-use crate::graph::{AudioGraph, AudioNode};
+use crate::graph::{AudioGraph, AudioNode, GraphError};
+use petgraph::graph::NodeIndex;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Creates nodes alike the node of `Op::Connect`, ref `build_graph` for the use case.
+pub type Factory = Box<dyn FnMut() -> AudioNode>;
+
+/// Creates ops of a quoted block for its run, counted from 0, ref `Op::Map`.
+pub type Quote = Box<dyn Fn(usize) -> Vec<Op>>;
+
+/// Maximum length of a list, so that a node connected to it and to one more node (like a step
+/// sequencer to its trigger) fits into `u8` inputs, ref `Op::ConnectList`.
+pub const MAX_LIST_LEN: usize = u8::MAX as usize - 1;
 
 pub enum Op {
-    Connect(AudioNode),
+    Connect(AudioNode, Option<Factory>),
+    /// Connect node which number of inputs depends on the length of the list: pop `inputs`
+    /// nodes and the list below them, then create the node for the list length and connect list
+    /// items followed by the nodes to it.
+    ConnectList {
+        inputs: u8,
+        node: Box<dyn FnOnce(usize) -> AudioNode>,
+    },
     Pop,
     Swap,
     Dup,
     Rot,
+    /// ( a b -- a b a )
+    Over,
+    /// ( a b -- b )
+    Nip,
+    /// ( a b -- b a b )
+    Tuck,
+    /// ( a b c -- c a b ), reverse of `Rot`.
+    RotBack,
+    /// ( a b -- a b a b )
+    Dup2,
+    /// ( a b c d -- c d a b )
+    Swap2,
+    /// Push a copy of the n-th item counting from the top one, which is 0th.
+    Pick(usize),
+    /// Move the n-th item counting from the top one, which is 0th, to the top.
+    Roll(usize),
+    /// Pop n items.
+    Drop(usize),
+    /// Pop the top item and bind it to the name.
+    Set(String),
+    /// Push the item bound to the name.
+    Get(String),
+    /// Start a list, items pushed until the matching `End` become its items.
+    Begin,
+    /// Finish the list and push it.
+    End,
+    /// Pop the list and run ops of the quote for each item of it, then push the list of the top
+    /// items each run leaves. Each run starts with the same stack with the item pushed, so the
+    /// items a run takes from below the item are shared by all runs, and the rest of what the
+    /// last run leaves stays on the stack.
+    Map(Quote),
+    /// Run ops of the quote `n` times as `Map` does, without pushing anything before each run.
+    Repeat(usize, Quote),
 }
 
+/// Error of building graph from ops. Each variant starts with the index of op which caused it.
 #[derive(Debug)]
 pub enum Error {
-    StackExhausted(usize),
+    /// Op needs more items than there are on the stack.
+    StackExhausted {
+        op: usize,
+        expected: usize,
+        available: usize,
+    },
+    InvalidWiring(usize, GraphError),
+    /// Nothing is bound to the name yet.
+    UnknownBinding(usize, String),
+    /// `End` without `Begin` or `Begin` without `End`.
+    UnbalancedList(usize),
+    /// Ops between `Begin` and `End` take items pushed before `Begin`.
+    ListUnderflow(usize),
+    /// Op expects a node, but the item is a list.
+    UnexpectedList(usize),
+    /// Op expects a list, but the item is a node.
+    ExpectedList(usize),
+    /// Node has no factory, so it can't be connected to lists.
+    NotExpandable(usize),
+    /// Run of a quote leaves no node on top of the stack, or opens or closes lists.
+    InvalidQuote(usize),
 }
 
-pub fn build_graph(mut ops: Vec<Op>, channels: u8, inputs: u8) -> Result<AudioGraph, Error> {
-    let mut graph = AudioGraph::new(channels, inputs);
-    let mut stack = Vec::new();
-    for (i, op) in ops.drain(..).enumerate() {
+impl Error {
+    /// Index of op which caused the error.
+    pub fn op(&self) -> usize {
+        match self {
+            Error::StackExhausted { op, .. } => *op,
+            Error::InvalidWiring(op, _) | Error::UnknownBinding(op, _) => *op,
+            Error::UnbalancedList(op)
+            | Error::ListUnderflow(op)
+            | Error::UnexpectedList(op)
+            | Error::ExpectedList(op)
+            | Error::NotExpandable(op)
+            | Error::InvalidQuote(op) => *op,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::StackExhausted {
+                expected,
+                available,
+                ..
+            } => write!(
+                f,
+                "stack exhausted: expected {} items, available {}",
+                expected, available
+            ),
+            Error::InvalidWiring(_, e) => write!(f, "invalid wiring: {}", e),
+            Error::UnknownBinding(_, name) => write!(f, "`{}` is not bound", name),
+            Error::UnbalancedList(_) => write!(f, "unbalanced list brackets"),
+            Error::ListUnderflow(_) => write!(f, "list takes items from outside of it"),
+            Error::UnexpectedList(_) => write!(f, "expected a node, found a list"),
+            Error::ExpectedList(_) => write!(f, "expected a list, found a node"),
+            Error::NotExpandable(_) => write!(f, "node can't be connected to lists"),
+            Error::InvalidQuote(_) => write!(
+                f,
+                "quote must leave a node on the stack and keep list brackets balanced"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Stack effect of `ops` as (inputs, outputs) pair: how many items they take from the stack and
+/// how many they leave in place of them.
+pub fn stack_effect(ops: &[Op]) -> (usize, usize) {
+    let (mut depth, mut inputs) = (0isize, 0isize);
+    // Depth at each open list.
+    let mut marks = Vec::new();
+    for op in ops {
+        let (pops, pushes) = match op {
+            Op::Connect(node, _) => (node.inputs() as isize, 1),
+            Op::ConnectList { inputs, .. } => (*inputs as isize + 1, 1),
+            Op::Pop => (1, 0),
+            Op::Dup => (1, 2),
+            Op::Swap => (2, 2),
+            Op::Rot => (3, 3),
+            Op::Over => (2, 3),
+            Op::Nip => (2, 1),
+            Op::Tuck => (2, 3),
+            Op::RotBack => (3, 3),
+            Op::Dup2 => (2, 4),
+            Op::Swap2 => (4, 4),
+            Op::Pick(n) => (*n as isize + 1, *n as isize + 2),
+            Op::Roll(n) => (*n as isize + 1, *n as isize + 1),
+            Op::Drop(n) => (*n as isize, 0),
+            Op::Set(_) => (1, 0),
+            Op::Get(_) => (0, 1),
+            Op::Begin => {
+                marks.push(depth);
+                (0, 0)
+            }
+            Op::End => ((depth - marks.pop().unwrap_or(depth)).max(0), 1),
+            Op::Map(quote) => {
+                // The item is pushed before each run.
+                let (inputs, outputs) = stack_effect(&quote(0));
+                let (inputs, outputs) = match inputs {
+                    0 => (0, outputs + 1),
+                    _ => (inputs - 1, outputs),
+                };
+                (inputs as isize + 1, outputs.max(1) as isize)
+            }
+            Op::Repeat(_, quote) => {
+                let (inputs, outputs) = stack_effect(&quote(0));
+                (inputs as isize, outputs.max(1) as isize)
+            }
+        };
+        depth -= pops;
+        inputs = inputs.max(-depth);
+        depth += pushes;
+    }
+    (inputs as usize, (inputs + depth) as usize)
+}
+
+/// Item on the stack, `T` identifies a node.
+#[derive(Clone)]
+enum Item<T> {
+    Node(T),
+    List(Vec<T>),
+}
+
+/// Stack of items with bindings and open lists. It applies all ops except those creating nodes,
+/// which is left to `build_graph` and `check`.
+struct Stack<T> {
+    items: Vec<Item<T>>,
+    bindings: HashMap<String, Item<T>>,
+    /// Index of `Begin` op and stack length at it for each open list.
+    marks: Vec<(usize, usize)>,
+}
+
+impl<T: Clone> Stack<T> {
+    fn new() -> Self {
+        Stack {
+            items: Vec::new(),
+            bindings: HashMap::new(),
+            marks: Vec::new(),
+        }
+    }
+
+    /// Apply op `i` if it doesn't create nodes, return whether it was applied.
+    fn apply(&mut self, i: usize, op: &Op) -> Result<bool, Error> {
+        let len = self.items.len();
         match op {
-            Op::Connect(node) => {
-                let inputs = node.inputs() as usize;
-                if stack.len() < inputs {
-                    return Err(Error::StackExhausted(i));
-                }
-                let idx = graph.add_node(node);
-                let start = stack.len() - inputs;
-                graph.set_sources(idx, &stack.drain(start..).collect::<Vec<_>>());
-                stack.push(idx);
+            Op::Connect(..) | Op::ConnectList { .. } | Op::Map(_) | Op::Repeat(..) => {
+                return Ok(false)
             }
             Op::Pop => {
-                let _ = stack.pop();
+                // Popping from the empty stack does nothing.
+                if len > 0 {
+                    self.require(i, 1)?;
+                    self.items.pop();
+                }
+            }
+            Op::Dup => {
+                self.require(i, 1)?;
+                self.items.push(self.items[len - 1].clone());
             }
-            Op::Dup => match stack.last().cloned() {
-                Some(idx) => stack.push(idx),
-                None => return Err(Error::StackExhausted(i)),
-            },
             Op::Swap => {
-                let len = stack.len();
-                if len < 2 {
-                    return Err(Error::StackExhausted(i));
-                }
-                stack.swap(len - 2, len - 1);
+                self.require(i, 2)?;
+                self.items.swap(len - 2, len - 1);
             }
             Op::Rot => {
-                let len = stack.len();
-                if len < 3 {
-                    return Err(Error::StackExhausted(i));
+                self.require(i, 3)?;
+                self.items[len - 3..].rotate_left(1);
+            }
+            Op::Over => {
+                self.require(i, 2)?;
+                self.items.push(self.items[len - 2].clone());
+            }
+            Op::Nip => {
+                self.require(i, 2)?;
+                self.items.remove(len - 2);
+            }
+            Op::Tuck => {
+                self.require(i, 2)?;
+                self.items.insert(len - 2, self.items[len - 1].clone());
+            }
+            Op::RotBack => {
+                self.require(i, 3)?;
+                self.items[len - 3..].rotate_right(1);
+            }
+            Op::Dup2 => {
+                self.require(i, 2)?;
+                self.items.extend_from_within(len - 2..);
+            }
+            Op::Swap2 => {
+                self.require(i, 4)?;
+                self.items[len - 4..].rotate_left(2);
+            }
+            Op::Pick(n) => {
+                self.require(i, n + 1)?;
+                self.items.push(self.items[len - 1 - n].clone());
+            }
+            Op::Roll(n) => {
+                self.require(i, n + 1)?;
+                self.items[len - 1 - n..].rotate_left(1);
+            }
+            Op::Drop(n) => {
+                self.require(i, *n)?;
+                self.items.truncate(len - n);
+            }
+            Op::Set(name) => {
+                self.require(i, 1)?;
+                if let Some(item) = self.items.pop() {
+                    self.bindings.insert(name.clone(), item);
+                }
+            }
+            Op::Get(name) => match self.bindings.get(name) {
+                Some(item) => self.items.push(item.clone()),
+                None => return Err(Error::UnknownBinding(i, name.clone())),
+            },
+            Op::Begin => self.marks.push((i, len)),
+            Op::End => {
+                let start = match self.marks.pop() {
+                    Some((_, start)) => start,
+                    None => return Err(Error::UnbalancedList(i)),
+                };
+                let list = self
+                    .items
+                    .drain(start..)
+                    .map(|item| match item {
+                        Item::Node(node) => Ok(node),
+                        Item::List(_) => Err(Error::UnexpectedList(i)),
+                    })
+                    .collect::<Result<_, _>>()?;
+                self.items.push(Item::List(list));
+            }
+        }
+        Ok(true)
+    }
+
+    /// Check that there are `n` items for op `i`, inside of the current list if any.
+    fn require(&self, i: usize, n: usize) -> Result<(), Error> {
+        let available = self.items.len();
+        if available < n {
+            return Err(Error::StackExhausted {
+                op: i,
+                expected: n,
+                available,
+            });
+        }
+        match self.marks.last() {
+            Some(&(_, start)) if available - n < start => Err(Error::ListUnderflow(i)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Pop `n` nodes for op `i`, in the order they were pushed.
+    fn pop_nodes(&mut self, i: usize, n: usize) -> Result<Vec<T>, Error> {
+        self.require(i, n)?;
+        let start = self.items.len() - n;
+        self.items
+            .drain(start..)
+            .map(|item| match item {
+                Item::Node(node) => Ok(node),
+                Item::List(_) => Err(Error::UnexpectedList(i)),
+            })
+            .collect()
+    }
+
+    /// Pop the list for op `i`.
+    fn pop_list(&mut self, i: usize) -> Result<Vec<T>, Error> {
+        self.require(i, 1)?;
+        match self.items.pop() {
+            Some(Item::List(list)) => Ok(list),
+            _ => Err(Error::ExpectedList(i)),
+        }
+    }
+
+    /// Pop sources for op `i` which creates nodes: items for `Connect` and the list followed by
+    /// nodes for `ConnectList`.
+    ///
+    /// Returns sources of each node to create and whether they make a list. `Connect` creates a
+    /// node for each item of the longest list among its sources, ref `build_graph`.
+    fn pop_sources(&mut self, i: usize, op: &Op) -> Result<(Vec<Vec<T>>, bool), Error> {
+        let (node, factory) = match op {
+            Op::Connect(node, factory) => (node, factory),
+            Op::ConnectList { inputs, .. } => {
+                let nodes = self.pop_nodes(i, *inputs as usize)?;
+                let mut list = self.pop_list(i)?;
+                list.extend(nodes);
+                return Ok((vec![list], false));
+            }
+            _ => return Ok((Vec::new(), false)),
+        };
+        let n = node.inputs() as usize;
+        self.require(i, n)?;
+        let start = self.items.len() - n;
+        let items = self.items.drain(start..).collect::<Vec<_>>();
+        let size = items
+            .iter()
+            .filter_map(|item| match item {
+                Item::Node(_) => None,
+                Item::List(list) => Some(list.len()),
+            })
+            .max();
+        let size = match size {
+            Some(size) => size,
+            None => {
+                let nodes = items.into_iter().map(|item| match item {
+                    Item::Node(node) => node,
+                    Item::List(_) => unreachable!(),
+                });
+                return Ok((vec![nodes.collect()], false));
+            }
+        };
+        if size > 1 && factory.is_none() {
+            return Err(Error::NotExpandable(i));
+        }
+        let sources = (0..size)
+            .map(|k| {
+                let source = |item: &Item<T>| match item {
+                    Item::Node(node) => node.clone(),
+                    // Shorter lists wrap around.
+                    Item::List(list) => list[k % list.len()].clone(),
+                };
+                items.iter().map(source).collect()
+            })
+            .collect();
+        Ok((sources, true))
+    }
+
+    /// Run op `i`, which is `Map` or `Repeat`, applying ops of each run with `run`.
+    fn run_quote<F>(&mut self, i: usize, op: &Op, mut run: F) -> Result<(), Error>
+    where
+        F: FnMut(&mut Self, Op) -> Result<(), Error>,
+    {
+        let (items, quote) = match op {
+            Op::Map(quote) => (self.pop_list(i)?.into_iter().map(Some).collect(), quote),
+            Op::Repeat(n, quote) => (vec![None; *n], quote),
+            _ => unreachable!(),
+        };
+        let (stack, marks) = (self.items.clone(), self.marks.clone());
+        let mut results = Vec::with_capacity(items.len());
+        for (k, item) in items.into_iter().enumerate() {
+            self.items.clone_from(&stack);
+            self.items.extend(item.map(Item::Node));
+            for op in quote(k) {
+                run(self, op)?;
+            }
+            if self.marks != marks {
+                return Err(Error::InvalidQuote(i));
+            }
+            match self.pop_nodes(i, 1) {
+                Ok(node) => results.extend(node),
+                Err(_) => return Err(Error::InvalidQuote(i)),
+            }
+        }
+        self.items.push(Item::List(results));
+        Ok(())
+    }
+
+    /// Node on top of the stack after the last op `i`, which is graph's output.
+    fn output(&self, i: usize) -> Result<Option<T>, Error> {
+        if let Some(&(begin, _)) = self.marks.last() {
+            return Err(Error::UnbalancedList(begin));
+        }
+        match self.items.last() {
+            Some(Item::Node(node)) => Ok(Some(node.clone())),
+            Some(Item::List(_)) => Err(Error::UnexpectedList(i)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Problem found by `check` which doesn't prevent building the graph. Each variant contains the
+/// index of op it's about.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Warning {
+    /// Node created by the op is left on the stack below the top item, so it's discarded.
+    Discarded(usize),
+    /// Output of the node created by the op is not used by any other node nor is graph's output.
+    Unused(usize),
+}
+
+impl Warning {
+    pub fn op(&self) -> usize {
+        match self {
+            Warning::Discarded(op) | Warning::Unused(op) => *op,
+        }
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Warning::Discarded(_) => write!(f, "item is left on the stack and discarded"),
+            Warning::Unused(_) => write!(f, "output of the node is never used"),
+        }
+    }
+}
+
+/// Result of `check`.
+#[derive(Clone, Debug, Default)]
+pub struct Check {
+    /// Stack depth after each op.
+    pub depths: Vec<usize>,
+    /// How many items are left on the stack in the end. Only the top one is graph's output.
+    pub outputs: usize,
+    /// Warnings sorted by op.
+    pub warnings: Vec<Warning>,
+}
+
+/// Check `ops` without building the graph: find errors `build_graph` would fail with (except
+/// wiring ones which depend on modules) and suspicious places in the wiring.
+pub fn check(ops: &[Op]) -> Result<Check, Error> {
+    // Nodes are identified by index of op which created them.
+    let mut stack = Stack::new();
+    let mut depths = Vec::with_capacity(ops.len());
+    let mut used = vec![false; ops.len()];
+    for (i, op) in ops.iter().enumerate() {
+        check_op(&mut stack, &mut used, i, op)?;
+        depths.push(stack.items.len());
+    }
+
+    let mut warnings = Vec::new();
+    if let Some(node) = stack.output(ops.len().saturating_sub(1))? {
+        used[node] = true;
+    }
+    let discarded = stack
+        .items
+        .iter()
+        .rev()
+        .skip(1)
+        .flat_map(|item| match item {
+            Item::Node(node) => vec![*node],
+            Item::List(list) => list.clone(),
+        });
+    for node in discarded {
+        if !used[node] {
+            warnings.push(Warning::Discarded(node));
+            // Don't warn twice about the same node.
+            used[node] = true;
+        }
+    }
+    for (i, op) in ops.iter().enumerate() {
+        if let Op::Connect(..) | Op::ConnectList { .. } | Op::Map(_) | Op::Repeat(..) = op {
+            if !used[i] {
+                warnings.push(Warning::Unused(i));
+            }
+        }
+    }
+    warnings.sort_by_key(Warning::op);
+    Ok(Check {
+        depths,
+        outputs: stack.items.len(),
+        warnings,
+    })
+}
+
+/// Apply op `i` for `check`. Nodes created by ops of quotes are identified by `i` too.
+fn check_op(stack: &mut Stack<usize>, used: &mut [bool], i: usize, op: &Op) -> Result<(), Error> {
+    if stack.apply(i, op)? {
+        return Ok(());
+    }
+    if let Op::Map(_) | Op::Repeat(..) = op {
+        stack.run_quote(i, op, |stack, op| check_op(stack, used, i, &op))?;
+        // Quote which creates no nodes, like `{ }`, pushes the items it's run with, and they
+        // are checked on their own.
+        if let Some(Item::List(list)) = stack.items.last() {
+            if !list.contains(&i) {
+                used[i] = true;
+            }
+        }
+        return Ok(());
+    }
+    let (sources, is_list) = stack.pop_sources(i, op)?;
+    for &node in sources.iter().flatten() {
+        used[node] = true;
+    }
+    stack.items.push(if is_list {
+        Item::List(vec![i; sources.len()])
+    } else {
+        Item::Node(i)
+    });
+    Ok(())
+}
+
+/// Build graph from `ops`. The node on top of the stack in the end is graph's output, the rest
+/// are discarded, ref `check`. The graph is optimized, ref `AudioGraph::optimize`.
+///
+/// When sources of `Connect` contain lists, the node is expanded: a node is created for each item
+/// of the longest list (the first one is op's node, the rest are made by its factory), and each
+/// of them is connected to items of the same index of lists (shorter lists wrap around) and to
+/// the same nodes. The list of created nodes is pushed, e.g. `[220 330 440] s` is a list of
+/// three oscillators.
+///
+/// Ops of quotes are run by `Map` and `Repeat` in place, errors in them are reported for the
+/// index of `Map` or `Repeat` op.
+pub fn build_graph(ops: Vec<Op>, channels: u8, inputs: u8) -> Result<AudioGraph, Error> {
+    build_graph_with_origins(ops, channels, inputs).map(|(graph, _)| graph)
+}
+
+/// Build graph as `build_graph` does and also return the index of op which created each node,
+/// indexed by `NodeIndex::index`, e.g. to point to the source of `AudioGraph::delayed_edges`.
+pub fn build_graph_with_origins(
+    ops: Vec<Op>,
+    channels: u8,
+    inputs: u8,
+) -> Result<(AudioGraph, Vec<usize>), Error> {
+    let mut graph = AudioGraph::new(channels, inputs);
+    let mut stack = Stack::new();
+    let mut origins = Vec::new();
+    let last = ops.len().saturating_sub(1);
+    for (i, op) in ops.into_iter().enumerate() {
+        build_op(&mut graph, &mut stack, &mut origins, i, op)?;
+    }
+    graph
+        .validate()
+        .map_err(|e| Error::InvalidWiring(last, e))?;
+    if let Some(idx) = stack.output(last)? {
+        // Every node on the stack is in the graph, so it can't fail.
+        let _ = graph.set_output(idx);
+    }
+    graph.optimize();
+    Ok((graph, origins))
+}
+
+/// Apply op `i` for `build_graph`.
+fn build_op(
+    graph: &mut AudioGraph,
+    stack: &mut Stack<NodeIndex>,
+    origins: &mut Vec<usize>,
+    i: usize,
+    op: Op,
+) -> Result<(), Error> {
+    if stack.apply(i, &op)? {
+        return Ok(());
+    }
+    if let Op::Map(_) | Op::Repeat(..) = op {
+        return stack.run_quote(i, &op, |stack, op| build_op(graph, stack, origins, i, op));
+    }
+    let (sources, is_list) = stack.pop_sources(i, &op)?;
+    let mut nodes = Vec::with_capacity(sources.len());
+    match op {
+        Op::Connect(node, factory) => {
+            nodes.push(node);
+            // Factory is checked by `pop_sources`.
+            if let Some(mut factory) = factory {
+                nodes.extend((1..sources.len()).map(|_| factory()));
+            }
+        }
+        Op::ConnectList { inputs, node } => {
+            nodes.push(node(sources[0].len() - inputs as usize));
+        }
+        _ => unreachable!(),
+    }
+    let mut indices = Vec::with_capacity(nodes.len());
+    for (node, sources) in nodes.into_iter().zip(sources) {
+        let idx = graph.add_node(node);
+        // Nodes are never removed while building, so indices go in order.
+        origins.push(i);
+        graph
+            .connect_sources(idx, &sources)
+            .map_err(|e| Error::InvalidWiring(i, e))?;
+        indices.push(idx);
+    }
+    stack.items.push(if is_list {
+        Item::List(indices)
+    } else {
+        Item::Node(indices[0])
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Apply `op` to the stack of nodes `1..=depth` and return nodes left on it.
+    fn apply(op: Op, depth: usize) -> Result<Vec<usize>, Error> {
+        let mut stack = Stack::new();
+        stack.items.extend((1..=depth).map(Item::Node));
+        assert!(stack.apply(0, &op)?);
+        let nodes = stack.items.into_iter().map(|item| match item {
+            Item::Node(node) => node,
+            Item::List(_) => unreachable!(),
+        });
+        Ok(nodes.collect())
+    }
+
+    #[test]
+    fn stack_words() {
+        let table = vec![
+            (Op::Pop, vec![1, 2, 3]),
+            (Op::Swap, vec![1, 2, 4, 3]),
+            (Op::Dup, vec![1, 2, 3, 4, 4]),
+            (Op::Rot, vec![1, 3, 4, 2]),
+            (Op::Over, vec![1, 2, 3, 4, 3]),
+            (Op::Nip, vec![1, 2, 4]),
+            (Op::Tuck, vec![1, 2, 4, 3, 4]),
+            (Op::RotBack, vec![1, 4, 2, 3]),
+            (Op::Dup2, vec![1, 2, 3, 4, 3, 4]),
+            (Op::Swap2, vec![3, 4, 1, 2]),
+            (Op::Pick(0), vec![1, 2, 3, 4, 4]),
+            (Op::Pick(3), vec![1, 2, 3, 4, 1]),
+            (Op::Roll(0), vec![1, 2, 3, 4]),
+            (Op::Roll(3), vec![2, 3, 4, 1]),
+            (Op::Drop(0), vec![1, 2, 3, 4]),
+            (Op::Drop(3), vec![1]),
+        ];
+        for (i, (op, expected)) in table.into_iter().enumerate() {
+            let (inputs, outputs) = stack_effect(std::slice::from_ref(&op));
+            assert_eq!(4 - inputs + outputs, expected.len(), "row {}", i);
+            assert_eq!(apply(op, 4).unwrap(), expected, "row {}", i);
+        }
+    }
+
+    #[test]
+    fn stack_words_underflow() {
+        let table = vec![
+            (Op::Over, 1, 2),
+            (Op::Nip, 1, 2),
+            (Op::Tuck, 1, 2),
+            (Op::RotBack, 2, 3),
+            (Op::Dup2, 1, 2),
+            (Op::Swap2, 3, 4),
+            (Op::Pick(4), 4, 5),
+            (Op::Roll(4), 4, 5),
+            (Op::Drop(5), 4, 5),
+        ];
+        for (op, depth, required) in table {
+            match apply(op, depth) {
+                Err(Error::StackExhausted {
+                    op: 0,
+                    expected,
+                    available,
+                }) => {
+                    assert_eq!(expected, required);
+                    assert_eq!(available, depth);
                 }
-                stack.swap(len - 2, len - 1);
-                stack.swap(len - 3, len - 1);
+                result => panic!("unexpected result: {:?}", result),
             }
         }
     }
-    Ok(graph)
 }
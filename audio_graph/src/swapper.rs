@@ -0,0 +1,343 @@
+//! # Graph swapper
+//!
+//! Replacing a playing graph with a new one at once produces a discontinuity in the output which
+//! is heard as a click. GraphSwapper runs both the old and the new graphs for a while and mixes
+//! their outputs with an equal-power crossfade instead.
+//!
+//! Before the crossfade state of the old graph is migrated to the new one, ref
+//! `AudioGraph::plan_migration`. Large state, like long delay lines, is copied in parts over
+//! several blocks to keep each of them short.
+use crate::graph::{AudioGraph, MAX_FRAMES};
+use crate::module::Module;
+use crate::sample::{Frame, Sample};
+use std::f64::consts::FRAC_PI_2;
+
+/// How many samples of state are migrated per block, ref `Module::migrate_part`.
+const MIGRATION_SAMPLES: usize = 1 << 18;
+
+pub struct GraphSwapper {
+    /// Graph which is playing or fading in.
+    graph: AudioGraph,
+    /// Graph which is fading out.
+    old: Option<AudioGraph>,
+    /// Graph which state is being migrated before it fades in, and how many samples of its
+    /// migration are done.
+    pending: Option<(AudioGraph, usize)>,
+    /// Graphs which are done playing. They are kept here until collected with `retired`, so that
+    /// deallocation (which might be costly for graphs with long delay lines) happens outside of
+    /// the audio thread. It never grows beyond its initial capacity, ref `retire`.
+    retired: Vec<AudioGraph>,
+    /// Crossfade duration.
+    fade_frames: usize,
+    /// How many frames of the current crossfade are already done.
+    position: usize,
+    /// Old graph's output during crossfade.
+    buffer: Vec<Sample>,
+    output: Vec<Sample>,
+}
+
+impl GraphSwapper {
+    pub fn new(graph: AudioGraph, fade_frames: usize) -> Self {
+        let channels = graph.output().len();
+        GraphSwapper {
+            graph,
+            old: None,
+            pending: None,
+            // Enough to keep all graphs retired by `swap` and `process` if retired graphs are
+            // collected before each swap.
+            retired: Vec::with_capacity(2),
+            fade_frames,
+            position: 0,
+            buffer: vec![0.0; MAX_FRAMES * channels],
+            output: vec![0.0; channels],
+        }
+    }
+
+    /// Graph which is playing or fading in.
+    pub fn graph(&self) -> &AudioGraph {
+        &self.graph
+    }
+
+    /// Set crossfade duration for the next swaps.
+    pub fn set_fade_frames(&mut self, fade_frames: usize) {
+        self.fade_frames = fade_frames;
+    }
+
+    /// Migrate state of the current graph to the new one and then start crossfade to it.
+    ///
+    /// Migration is done in the following blocks, it takes one block unless the graph has large
+    /// state, ref `Module::migration_size`. Plan migration in advance to not allocate, ref
+    /// `AudioGraph::plan_migration`. If another graph is swapped in before migration is done,
+    /// the pending one is skipped.
+    pub fn swap(&mut self, graph: AudioGraph) {
+        if let Some((skipped, _)) = self.pending.replace((graph, 0)) {
+            self.retire(skipped);
+        }
+    }
+
+    /// Continue migration of the pending graph, if any, and start crossfade when it's done.
+    fn migrate(&mut self) {
+        let (graph, done) = match &mut self.pending {
+            Some(pending) => pending,
+            None => return,
+        };
+        let size = graph.migration_size();
+        let end = size.min(*done + MIGRATION_SAMPLES);
+        graph.migrate_part(&self.graph, *done, end);
+        *done = end;
+        if end < size {
+            return;
+        }
+        if let Some((mut graph, _)) = self.pending.take() {
+            graph.migrate(&self.graph);
+            self.fade_to(graph);
+        }
+    }
+
+    /// Start crossfade from the current graph to the new one.
+    ///
+    /// If the previous crossfade is not finished yet, the graph which was fading out is cut off
+    /// and the graph which was fading in starts to fade out from the full volume.
+    fn fade_to(&mut self, graph: AudioGraph) {
+        let old = std::mem::replace(&mut self.graph, graph);
+        // Graph which was fading out is interrupted.
+        if let Some(interrupted) = self.old.take() {
+            self.retire(interrupted);
+        }
+        if self.fade_frames > 0 {
+            self.old = Some(old);
+            self.position = 0;
+        } else {
+            self.retire(old);
+        }
+    }
+
+    /// Take one of the graphs which are done playing, if any.
+    /// Call it outside of the audio thread until it returns `None` and drop the result there.
+    pub fn retired(&mut self) -> Option<AudioGraph> {
+        self.retired.pop()
+    }
+
+    /// Keep graph which is done playing until it's collected with `retired`.
+    ///
+    /// If retired graphs are not collected and there is no room left, graph is dropped right here
+    /// as the last resort rather than growing the buffer on the audio thread.
+    fn retire(&mut self, graph: AudioGraph) {
+        if self.retired.len() < self.retired.capacity() {
+            self.retired.push(graph);
+        } else {
+            drop(graph);
+        }
+    }
+
+    /// Compute `frames` frames of the graph, crossfading with the old one if needed.
+    fn render(&mut self, input: &[Sample], output: &mut [Sample], frames: usize) {
+        self.migrate();
+        let input_size = input.len() / frames;
+        let channels = output.len() / frames;
+        for (i, output) in output.chunks_mut(MAX_FRAMES * channels).enumerate() {
+            let n = output.len() / channels;
+            let start = i * MAX_FRAMES * input_size;
+            let input = &input[start..start + n * input_size];
+            self.graph.process(input, output, n);
+            let old = match &mut self.old {
+                Some(old) => old,
+                None => continue,
+            };
+            let buffer = &mut self.buffer[..n * channels];
+            old.process(input, buffer, n);
+            for (frame, old_frame) in output.chunks_mut(channels).zip(buffer.chunks(channels)) {
+                let t = (self.position as Sample / self.fade_frames as Sample).min(1.0);
+                let (fade_in, fade_out) = (t * FRAC_PI_2).sin_cos();
+                for (x, y) in frame.iter_mut().zip(old_frame) {
+                    *x = *x * fade_in + *y * fade_out;
+                }
+                self.position += 1;
+            }
+            if self.position >= self.fade_frames {
+                if let Some(old) = self.old.take() {
+                    self.retire(old);
+                }
+            }
+        }
+    }
+}
+
+impl Module for GraphSwapper {
+    fn inputs(&self) -> u8 {
+        self.graph.inputs()
+    }
+
+    fn output(&self) -> &Frame {
+        &self.output
+    }
+
+    fn sample(&mut self, input: &Frame) {
+        let mut output = std::mem::take(&mut self.output);
+        self.render(input, &mut output, 1);
+        self.output = output;
+    }
+
+    fn process(&mut self, input: &[Sample], output: &mut [Sample], frames: usize) {
+        if frames == 0 {
+            return;
+        }
+        self.render(input, output, frames);
+        let channels = self.output.len();
+        self.output
+            .copy_from_slice(&output[output.len() - channels..]);
+    }
+
+    /// Reset the graph and cut off the one fading out, if any.
+    fn reset(&mut self) {
+        self.graph.reset();
+        if let Some(old) = self.old.take() {
+            self.retire(old);
+        }
+        self.output.iter_mut().for_each(|x| *x = 0.0);
+    }
+
+    /// Note that crossfade duration is in frames and is not changed, ref `set_fade_frames`.
+    fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.graph.set_sample_rate(sample_rate);
+        if let Some(old) = &mut self.old {
+            old.set_sample_rate(sample_rate);
+        }
+        if let Some((pending, _)) = &mut self.pending {
+            pending.set_sample_rate(sample_rate);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Module with large state, which counts how many parts of it are migrated and adds 100
+    /// when migration is finished.
+    struct Large(Vec<Sample>);
+
+    impl Module for Large {
+        fn inputs(&self) -> u8 {
+            0
+        }
+
+        fn output(&self) -> &Frame {
+            &self.0
+        }
+
+        fn sample(&mut self, _input: &Frame) {}
+
+        fn migrate(&mut self, _old: &dyn Module) {
+            self.0[0] += 100.0;
+        }
+
+        fn migration_size(&self) -> usize {
+            2 * MIGRATION_SAMPLES + 1
+        }
+
+        fn migrate_part(&mut self, _old: &dyn Module, _start: usize, _end: usize) {
+            self.0[0] += 1.0;
+        }
+    }
+
+    fn large() -> AudioGraph {
+        let mut graph = AudioGraph::new(1, 0);
+        let idx = graph.add_node(Box::new(Large(vec![0.0])));
+        graph.set_output(idx).unwrap();
+        graph.update_order();
+        graph
+    }
+
+    struct Const(Vec<Sample>);
+
+    impl Module for Const {
+        fn inputs(&self) -> u8 {
+            0
+        }
+
+        fn output(&self) -> &Frame {
+            &self.0
+        }
+
+        fn sample(&mut self, _input: &Frame) {}
+    }
+
+    fn constant(x: Sample) -> AudioGraph {
+        let mut graph = AudioGraph::new(1, 0);
+        let idx = graph.add_node(Box::new(Const(vec![x])));
+        graph.set_output(idx).unwrap();
+        graph.update_order();
+        graph
+    }
+
+    fn render(swapper: &mut GraphSwapper, frames: usize) -> Vec<Sample> {
+        let mut output = vec![0.0; frames];
+        swapper.process(&[], &mut output, frames);
+        output
+    }
+
+    #[test]
+    fn crossfade_is_equal_power() {
+        let mut fade_out = GraphSwapper::new(constant(1.0), 100);
+        fade_out.swap(constant(0.0));
+        let mut fade_in = GraphSwapper::new(constant(0.0), 100);
+        fade_in.swap(constant(1.0));
+        let (fade_out, fade_in) = (render(&mut fade_out, 120), render(&mut fade_in, 120));
+        for (i, (x, y)) in fade_out.iter().zip(&fade_in).enumerate() {
+            let t = (i as Sample / 100.0).min(1.0);
+            assert!((x - (t * FRAC_PI_2).cos()).abs() < 1e-12);
+            assert!((x * x + y * y - 1.0).abs() < 1e-12);
+        }
+        assert_eq!(fade_in[0], 0.0);
+        assert_eq!(fade_in[100..], [1.0; 20]);
+    }
+
+    #[test]
+    fn old_graph_is_retired_after_crossfade() {
+        let mut swapper = GraphSwapper::new(constant(1.0), 10);
+        swapper.swap(constant(2.0));
+        render(&mut swapper, 9);
+        assert!(swapper.retired().is_none());
+        render(&mut swapper, 1);
+        let old = swapper.retired().unwrap();
+        assert_eq!(old.output(), &[1.0]);
+        assert!(swapper.retired().is_none());
+        assert_eq!(swapper.graph().output(), &[2.0]);
+    }
+
+    #[test]
+    fn swap_during_crossfade() {
+        let mut swapper = GraphSwapper::new(constant(1.0), 10);
+        swapper.swap(constant(2.0));
+        render(&mut swapper, 5);
+        swapper.swap(constant(3.0));
+        // The graph fading out is cut off and the one fading in fades out from the full volume.
+        let output = render(&mut swapper, 11);
+        assert_eq!(output[0], 2.0);
+        assert_eq!(output[10], 3.0);
+        let mut retired = std::iter::from_fn(|| swapper.retired())
+            .map(|graph| graph.output()[0])
+            .collect::<Vec<_>>();
+        retired.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(retired, vec![1.0, 2.0]);
+    }
+
+    #[test]
+    fn large_state_is_migrated_over_several_blocks() {
+        let old = large();
+        let mut new = large();
+        new.plan_migration(&old.layout());
+        let mut swapper = GraphSwapper::new(old, 0);
+        swapper.swap(new);
+        let mut output = vec![0.0; 1];
+        let mut outputs = Vec::new();
+        for _ in 0..4 {
+            swapper.process(&[], &mut output, 1);
+            outputs.push(output[0]);
+        }
+        // The old graph plays until the third part is migrated.
+        assert_eq!(outputs, vec![0.0, 0.0, 103.0, 103.0]);
+        assert!(swapper.retired().is_some());
+    }
+}
@@ -1,6 +1,7 @@
 //! BiQuad Filters
 //!
 //! Sources to connect: input, cut-off frequency, Q.
+use crate::state;
 use audio_graph::{Frame, Module, Sample};
 
 type MakeCoefficients =
@@ -55,6 +56,10 @@ impl Module for BiQuad {
         3
     }
 
+    fn ports(&self) -> &'static [&'static str] {
+        &["input", "frequency", "q"]
+    }
+
     fn output(&self) -> &Frame {
         &self.output
     }
@@ -85,4 +90,24 @@ impl Module for BiQuad {
             *y2 = y1;
         }
     }
+
+    fn migrate(&mut self, old: &dyn Module) {
+        if let Some(old) = old.as_any().downcast_ref::<Self>() {
+            state::copy(&mut self.output, &old.output);
+            state::copy(&mut self.x1, &old.x1);
+            state::copy(&mut self.x2, &old.x2);
+            state::copy(&mut self.y2, &old.y2);
+        }
+    }
+
+    fn reset(&mut self) {
+        state::clear(&mut self.output);
+        state::clear(&mut self.x1);
+        state::clear(&mut self.x2);
+        state::clear(&mut self.y2);
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_angular_period = 2.0 * std::f64::consts::PI / Sample::from(sample_rate);
+    }
 }
@@ -0,0 +1,49 @@
+//! # Channel
+//!
+//! Output the given channel of the input in all channels.
+//!
+//! Sources to connect: input.
+use audio_graph::{Frame, Module, Sample};
+
+pub struct Channel {
+    index: usize,
+    output: Vec<Sample>,
+}
+
+impl Channel {
+    pub fn new(channels: u8, index: u8) -> Self {
+        Channel {
+            index: index as _,
+            output: vec![0.0; channels as _],
+        }
+    }
+}
+
+impl Module for Channel {
+    fn inputs(&self) -> u8 {
+        1
+    }
+
+    fn ports(&self) -> &'static [&'static str] {
+        &["input"]
+    }
+
+    fn output(&self) -> &Frame {
+        &self.output
+    }
+
+    fn sample(&mut self, input: &Frame) {
+        let value = input.get(self.index).cloned().unwrap_or(0.0);
+        for output in self.output.iter_mut() {
+            *output = value;
+        }
+    }
+
+    fn is_pure(&self) -> bool {
+        true
+    }
+
+    fn key(&self) -> Option<Vec<u64>> {
+        Some(vec![self.index as u64])
+    }
+}
@@ -27,4 +27,18 @@ impl Module for Constant {
     }
 
     fn sample(&mut self, _input: &Frame) {}
+
+    fn process(&mut self, _input: &[Sample], output: &mut [Sample], _frames: usize) {
+        for frame in output.chunks_mut(self.values.len()) {
+            frame.copy_from_slice(&self.values);
+        }
+    }
+
+    fn is_pure(&self) -> bool {
+        true
+    }
+
+    fn key(&self) -> Option<Vec<u64>> {
+        Some(self.values.iter().map(|x| x.to_bits()).collect())
+    }
 }
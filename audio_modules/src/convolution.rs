@@ -3,6 +3,7 @@
 //! Convolve two signals by making dot-product of a 3-sample sliding window on both.
 //!
 //! Sources to connect: input and kernel, but roles are vague in this case.
+use crate::state;
 use audio_graph::{Frame, Module, Sample};
 use std::collections::VecDeque;
 
@@ -34,6 +35,10 @@ impl Module for Convolution {
         2
     }
 
+    fn ports(&self) -> &'static [&'static str] {
+        &["input", "kernel"]
+    }
+
     fn output(&self) -> &Frame {
         &self.output
     }
@@ -49,6 +54,22 @@ impl Module for Convolution {
             *output = window.iter().sum();
         }
     }
+
+    fn migrate(&mut self, old: &dyn Module) {
+        if let Some(old) = old.as_any().downcast_ref::<Self>() {
+            for (window, old_window) in self.windows.iter_mut().zip(&old.windows) {
+                state::copy(window, old_window);
+            }
+            state::copy(&mut self.output, &old.output);
+        }
+    }
+
+    fn reset(&mut self) {
+        for window in self.windows.iter_mut() {
+            state::clear(window);
+        }
+        state::clear(&mut self.output);
+    }
 }
 
 pub struct ConvolutionM {
@@ -81,6 +102,10 @@ impl Module for ConvolutionM {
         1 + self.window_size as u8
     }
 
+    fn ports(&self) -> &'static [&'static str] {
+        &["input"]
+    }
+
     fn output(&self) -> &Frame {
         &self.output
     }
@@ -100,4 +125,20 @@ impl Module for ConvolutionM {
             *output = result;
         }
     }
+
+    fn migrate(&mut self, old: &dyn Module) {
+        if let Some(old) = old.as_any().downcast_ref::<Self>() {
+            for (window, old_window) in self.windows.iter_mut().zip(&old.windows) {
+                state::copy(window, old_window);
+            }
+            state::copy(&mut self.output, &old.output);
+        }
+    }
+
+    fn reset(&mut self) {
+        for window in self.windows.iter_mut() {
+            state::clear(window);
+        }
+        state::clear(&mut self.output);
+    }
 }
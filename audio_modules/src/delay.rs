@@ -3,6 +3,7 @@
 //! Variable signal delay up to maximum period.
 //!
 //! Sources to connect: input to delay, delay time.
+use crate::state;
 use audio_graph::{Frame, Module, Sample};
 use std::collections::VecDeque;
 
@@ -11,15 +12,16 @@ pub struct Delay {
     mask: usize,
     frame_number: usize,
     sample_rate: Sample,
+    max_delay: f64,
     output: Vec<Sample>,
+    /// Frame number of the old delay when migration in parts started, ref `migrate_part`.
+    synced: Option<usize>,
 }
 
 impl Delay {
     pub fn new(channels: u8, sample_rate: u32, max_delay: f64) -> Self {
         let sample_rate = Sample::from(sample_rate);
-        // +1 because interpolation looks for the next sample
-        // next_power_of_two to trade memory for speed by replacing `mod` with `&`
-        let max_delay_frames = ((sample_rate * max_delay) as usize + 1).next_power_of_two();
+        let max_delay_frames = max_delay_frames(sample_rate, max_delay);
         let mask = max_delay_frames - 1;
         let mut buffers = Vec::with_capacity(channels as _);
         for _ in 0..channels {
@@ -35,15 +37,28 @@ impl Delay {
             mask,
             output: vec![0.0; channels as _],
             sample_rate,
+            max_delay,
+            synced: None,
         }
     }
 }
 
+/// Length of the delay line which fits `max_delay` seconds.
+fn max_delay_frames(sample_rate: Sample, max_delay: f64) -> usize {
+    // +1 because interpolation looks for the next sample
+    // next_power_of_two to trade memory for speed by replacing `mod` with `&`
+    ((sample_rate * max_delay) as usize + 1).next_power_of_two()
+}
+
 impl Module for Delay {
     fn inputs(&self) -> u8 {
         2
     }
 
+    fn ports(&self) -> &'static [&'static str] {
+        &["input", "delay"]
+    }
+
     fn output(&self) -> &Frame {
         &self.output
     }
@@ -66,4 +81,75 @@ impl Module for Delay {
         }
         self.frame_number += 1;
     }
+
+    /// Catch up with the old delay if lines were copied with `migrate_part`: shift them by the
+    /// number of frames passed since then and copy only the most recent input.
+    fn migrate(&mut self, old: &dyn Module) {
+        if let Some(old) = old.as_any().downcast_ref::<Self>() {
+            let passed = self
+                .synced
+                .take()
+                .and_then(|synced| old.frame_number.checked_sub(synced))
+                .filter(|&passed| passed < self.mask + 1);
+            for (buffer, old_buffer) in self.buffers.iter_mut().zip(&old.buffers) {
+                match passed {
+                    Some(passed) => {
+                        buffer.rotate_right(passed);
+                        state::clear(buffer.range_mut(..passed));
+                        state::copy(buffer.range_mut(..passed), old_buffer);
+                    }
+                    None => state::copy(buffer, old_buffer),
+                }
+            }
+            state::copy(&mut self.output, &old.output);
+            self.frame_number = old.frame_number;
+        }
+    }
+
+    fn migration_size(&self) -> usize {
+        self.buffers.len() * (self.mask + 1)
+    }
+
+    /// Copy delay lines as they were when the first part was copied, compensating for the input
+    /// the old delay received since then.
+    fn migrate_part(&mut self, old: &dyn Module, start: usize, end: usize) {
+        if let Some(old) = old.as_any().downcast_ref::<Self>() {
+            let synced = *self.synced.get_or_insert(old.frame_number);
+            let passed = old.frame_number.saturating_sub(synced);
+            let size = self.mask + 1;
+            for (channel, (buffer, old_buffer)) in
+                self.buffers.iter_mut().zip(&old.buffers).enumerate()
+            {
+                // Part of the channel's line within `start..end`.
+                let from = start.max(channel * size) - channel * size;
+                let to = end.min((channel + 1) * size).saturating_sub(channel * size);
+                if from >= to || from + passed >= old_buffer.len() {
+                    continue;
+                }
+                state::copy(
+                    buffer.range_mut(from..to),
+                    old_buffer.range(from + passed..),
+                );
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        for buffer in self.buffers.iter_mut() {
+            state::clear(buffer);
+        }
+        state::clear(&mut self.output);
+        self.frame_number = 0;
+        self.synced = None;
+    }
+
+    /// Resize delay lines to fit `max_delay` in the new rate, keeping the most recent input.
+    fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = Sample::from(sample_rate);
+        let max_delay_frames = max_delay_frames(self.sample_rate, self.max_delay);
+        self.mask = max_delay_frames - 1;
+        for buffer in self.buffers.iter_mut() {
+            buffer.resize(max_delay_frames, 0.0);
+        }
+    }
 }
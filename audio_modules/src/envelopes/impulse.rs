@@ -1,3 +1,4 @@
+use crate::state;
 use audio_graph::{Frame, Module, Sample};
 
 pub struct Impulse {
@@ -26,6 +27,10 @@ impl Module for Impulse {
         2
     }
 
+    fn ports(&self) -> &'static [&'static str] {
+        &["trigger", "apex"]
+    }
+
     fn output(&self) -> &Frame {
         &self.output
     }
@@ -49,4 +54,24 @@ impl Module for Impulse {
         }
         self.frame += 1;
     }
+
+    fn migrate(&mut self, old: &dyn Module) {
+        if let Some(old) = old.as_any().downcast_ref::<Self>() {
+            state::copy(&mut self.last_trigger, &old.last_trigger);
+            state::copy(&mut self.output, &old.output);
+            state::copy(&mut self.trigger_frame, &old.trigger_frame);
+            self.frame = old.frame;
+        }
+    }
+
+    fn reset(&mut self) {
+        state::clear(&mut self.last_trigger);
+        state::clear(&mut self.output);
+        state::clear(&mut self.trigger_frame);
+        self.frame = 0;
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_period = Sample::from(sample_rate).recip();
+    }
 }
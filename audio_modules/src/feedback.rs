@@ -4,6 +4,7 @@
 //!
 //! Sources to connect: input to delay, delay time, gain.
 use crate::delay::Delay;
+use crate::state;
 use audio_graph::{Frame, Module, Sample};
 
 pub struct Feedback {
@@ -28,6 +29,10 @@ impl Module for Feedback {
         3
     }
 
+    fn ports(&self) -> &'static [&'static str] {
+        &["input", "delay", "gain"]
+    }
+
     fn output(&self) -> &Frame {
         &self.output
     }
@@ -54,4 +59,30 @@ impl Module for Feedback {
             *output = x + gain * delayed;
         }
     }
+
+    fn migrate(&mut self, old: &dyn Module) {
+        if let Some(old) = old.as_any().downcast_ref::<Self>() {
+            self.delay.migrate(&old.delay);
+            state::copy(&mut self.output, &old.output);
+        }
+    }
+
+    fn migration_size(&self) -> usize {
+        self.delay.migration_size()
+    }
+
+    fn migrate_part(&mut self, old: &dyn Module, start: usize, end: usize) {
+        if let Some(old) = old.as_any().downcast_ref::<Self>() {
+            self.delay.migrate_part(&old.delay, start, end);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.delay.reset();
+        state::clear(&mut self.output);
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.delay.set_sample_rate(sample_rate);
+    }
 }
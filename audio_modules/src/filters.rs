@@ -3,6 +3,7 @@
 //! Basic IIR low/high-pass filters.
 //!
 //! Sources to connect: input, cut-off frequency.
+use crate::state;
 use audio_graph::{Frame, Module, Sample};
 
 pub struct LPF {
@@ -25,6 +26,10 @@ impl Module for LPF {
         2
     }
 
+    fn ports(&self) -> &'static [&'static str] {
+        &["input", "frequency"]
+    }
+
     fn output(&self) -> &Frame {
         &self.output
     }
@@ -38,6 +43,20 @@ impl Module for LPF {
             *output += a * (x - *output);
         }
     }
+
+    fn migrate(&mut self, old: &dyn Module) {
+        if let Some(old) = old.as_any().downcast_ref::<Self>() {
+            state::copy(&mut self.output, &old.output);
+        }
+    }
+
+    fn reset(&mut self) {
+        state::clear(&mut self.output);
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_angular_period = 2.0 * std::f64::consts::PI / Sample::from(sample_rate);
+    }
 }
 
 pub struct HPF {
@@ -62,6 +81,10 @@ impl Module for HPF {
         2
     }
 
+    fn ports(&self) -> &'static [&'static str] {
+        &["input", "frequency"]
+    }
+
     fn output(&self) -> &Frame {
         &self.output
     }
@@ -80,4 +103,20 @@ impl Module for HPF {
             *x_prime = x;
         }
     }
+
+    fn migrate(&mut self, old: &dyn Module) {
+        if let Some(old) = old.as_any().downcast_ref::<Self>() {
+            state::copy(&mut self.output, &old.output);
+            state::copy(&mut self.x_prime, &old.x_prime);
+        }
+    }
+
+    fn reset(&mut self) {
+        state::clear(&mut self.output);
+        state::clear(&mut self.x_prime);
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_angular_period = 2.0 * std::f64::consts::PI / Sample::from(sample_rate);
+    }
 }
@@ -24,6 +24,10 @@ impl Module for Fn1 {
         1
     }
 
+    fn ports(&self) -> &'static [&'static str] {
+        &["x"]
+    }
+
     fn output(&self) -> &Frame {
         &self.ys
     }
@@ -33,6 +37,23 @@ impl Module for Fn1 {
             *y = (self.f)(*x);
         }
     }
+
+    fn process(&mut self, input: &[Sample], output: &mut [Sample], _frames: usize) {
+        for (y, x) in output.iter_mut().zip(input) {
+            *y = (self.f)(*x);
+        }
+        if let Some(last) = output.rchunks(self.ys.len()).next() {
+            self.ys.copy_from_slice(last);
+        }
+    }
+
+    fn is_pure(&self) -> bool {
+        true
+    }
+
+    fn key(&self) -> Option<Vec<u64>> {
+        Some(vec![self.f as usize as u64])
+    }
 }
 
 pub struct Fn2 {
@@ -54,6 +75,10 @@ impl Module for Fn2 {
         2
     }
 
+    fn ports(&self) -> &'static [&'static str] {
+        &["x", "y"]
+    }
+
     fn output(&self) -> &Frame {
         &self.ys
     }
@@ -63,6 +88,23 @@ impl Module for Fn2 {
             *y = (self.f)(x[0], x[1]);
         }
     }
+
+    fn process(&mut self, input: &[Sample], output: &mut [Sample], _frames: usize) {
+        for (y, x) in output.iter_mut().zip(input.chunks(2)) {
+            *y = (self.f)(x[0], x[1]);
+        }
+        if let Some(last) = output.rchunks(self.ys.len()).next() {
+            self.ys.copy_from_slice(last);
+        }
+    }
+
+    fn is_pure(&self) -> bool {
+        true
+    }
+
+    fn key(&self) -> Option<Vec<u64>> {
+        Some(vec![self.f as usize as u64])
+    }
 }
 
 pub struct Fn3 {
@@ -84,6 +126,10 @@ impl Module for Fn3 {
         3
     }
 
+    fn ports(&self) -> &'static [&'static str] {
+        &["x", "y", "z"]
+    }
+
     fn output(&self) -> &Frame {
         &self.ys
     }
@@ -93,4 +139,21 @@ impl Module for Fn3 {
             *y = (self.f)(x[0], x[1], x[2]);
         }
     }
+
+    fn process(&mut self, input: &[Sample], output: &mut [Sample], _frames: usize) {
+        for (y, x) in output.iter_mut().zip(input.chunks(3)) {
+            *y = (self.f)(x[0], x[1], x[2]);
+        }
+        if let Some(last) = output.rchunks(self.ys.len()).next() {
+            self.ys.copy_from_slice(last);
+        }
+    }
+
+    fn is_pure(&self) -> bool {
+        true
+    }
+
+    fn key(&self) -> Option<Vec<u64>> {
+        Some(vec![self.f as usize as u64])
+    }
 }
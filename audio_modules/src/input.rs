@@ -31,4 +31,8 @@ impl Module for Input {
         let channels = self.output.len();
         self.output.clone_from_slice(&input[..channels]);
     }
+
+    fn key(&self) -> Option<Vec<u64>> {
+        Some(Vec::new())
+    }
 }
@@ -2,6 +2,7 @@
 extern crate itertools;
 
 mod biquad;
+mod channel;
 mod constant;
 mod convolution;
 mod delay;
@@ -11,6 +12,7 @@ mod filters;
 mod function;
 mod input;
 mod metro;
+mod mix;
 mod noise;
 mod osc;
 mod pan;
@@ -19,12 +21,14 @@ mod phasor;
 mod pulse;
 pub mod pure;
 mod sample_and_hold;
+mod seq;
 mod spectral_transform;
+mod state;
 mod yin;
 mod zip;
 
 pub use self::{
-    biquad::*, constant::*, convolution::*, delay::*, feedback::*, filters::*, function::*,
-    input::*, metro::*, noise::*, osc::*, pan::*, parameter::*, phasor::*, pulse::*,
-    sample_and_hold::*, spectral_transform::*, yin::*, zip::*,
+    biquad::*, channel::*, constant::*, convolution::*, delay::*, feedback::*, filters::*,
+    function::*, input::*, metro::*, mix::*, noise::*, osc::*, pan::*, parameter::*, phasor::*,
+    pulse::*, sample_and_hold::*, seq::*, spectral_transform::*, yin::*, zip::*,
 };
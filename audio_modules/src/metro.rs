@@ -1,3 +1,4 @@
+use crate::state;
 use audio_graph::{Frame, Module, Sample};
 
 pub struct Metro {
@@ -23,6 +24,10 @@ impl Module for Metro {
         1
     }
 
+    fn ports(&self) -> &'static [&'static str] {
+        &["frequency"]
+    }
+
     fn output(&self) -> &Frame {
         &self.output
     }
@@ -41,6 +46,24 @@ impl Module for Metro {
         }
         self.frame_number += 1;
     }
+
+    fn migrate(&mut self, old: &dyn Module) {
+        if let Some(old) = old.as_any().downcast_ref::<Self>() {
+            state::copy(&mut self.output, &old.output);
+            state::copy(&mut self.last_trigger, &old.last_trigger);
+            self.frame_number = old.frame_number;
+        }
+    }
+
+    fn reset(&mut self) {
+        state::clear(&mut self.output);
+        state::clear(&mut self.last_trigger);
+        self.frame_number = 0;
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = Sample::from(sample_rate);
+    }
 }
 
 pub struct DMetro {
@@ -66,6 +89,10 @@ impl Module for DMetro {
         1
     }
 
+    fn ports(&self) -> &'static [&'static str] {
+        &["period"]
+    }
+
     fn output(&self) -> &Frame {
         &self.output
     }
@@ -84,6 +111,24 @@ impl Module for DMetro {
         }
         self.frame_number += 1;
     }
+
+    fn migrate(&mut self, old: &dyn Module) {
+        if let Some(old) = old.as_any().downcast_ref::<Self>() {
+            state::copy(&mut self.output, &old.output);
+            state::copy(&mut self.last_trigger, &old.last_trigger);
+            self.frame_number = old.frame_number;
+        }
+    }
+
+    fn reset(&mut self) {
+        state::clear(&mut self.output);
+        state::clear(&mut self.last_trigger);
+        self.frame_number = 0;
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = Sample::from(sample_rate);
+    }
 }
 
 pub struct MetroHold {
@@ -111,6 +156,10 @@ impl Module for MetroHold {
         1
     }
 
+    fn ports(&self) -> &'static [&'static str] {
+        &["frequency"]
+    }
+
     fn output(&self) -> &Frame {
         &self.output
     }
@@ -136,6 +185,26 @@ impl Module for MetroHold {
         }
         self.frame_number += 1;
     }
+
+    fn migrate(&mut self, old: &dyn Module) {
+        if let Some(old) = old.as_any().downcast_ref::<Self>() {
+            state::copy(&mut self.output, &old.output);
+            state::copy(&mut self.frequencies, &old.frequencies);
+            state::copy(&mut self.last_trigger, &old.last_trigger);
+            self.frame_number = old.frame_number;
+        }
+    }
+
+    fn reset(&mut self) {
+        state::clear(&mut self.output);
+        state::clear(&mut self.frequencies);
+        state::clear(&mut self.last_trigger);
+        self.frame_number = 0;
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = Sample::from(sample_rate);
+    }
 }
 
 pub struct DMetroHold {
@@ -163,6 +232,10 @@ impl Module for DMetroHold {
         1
     }
 
+    fn ports(&self) -> &'static [&'static str] {
+        &["period"]
+    }
+
     fn output(&self) -> &Frame {
         &self.output
     }
@@ -188,4 +261,24 @@ impl Module for DMetroHold {
         }
         self.frame_number += 1;
     }
+
+    fn migrate(&mut self, old: &dyn Module) {
+        if let Some(old) = old.as_any().downcast_ref::<Self>() {
+            state::copy(&mut self.output, &old.output);
+            state::copy(&mut self.dts, &old.dts);
+            state::copy(&mut self.last_trigger, &old.last_trigger);
+            self.frame_number = old.frame_number;
+        }
+    }
+
+    fn reset(&mut self) {
+        state::clear(&mut self.output);
+        state::clear(&mut self.dts);
+        state::clear(&mut self.last_trigger);
+        self.frame_number = 0;
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = Sample::from(sample_rate);
+    }
 }
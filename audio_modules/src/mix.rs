@@ -0,0 +1,112 @@
+//! # Mix
+//!
+//! Mix sums its inputs, e.g. voices of a chord made with multichannel expansion.
+//!
+//! Splay spreads its inputs evenly from the left to the right (first channel of each input is
+//! taken) and sums them, with level compensated for the number of inputs.
+//!
+//! Sources to connect: any number of inputs.
+use crate::pure;
+use audio_graph::{Frame, Module, Sample};
+
+pub struct Mix {
+    size: usize,
+    output: Vec<Sample>,
+}
+
+impl Mix {
+    /// Mix of `size` inputs, which must not exceed `u8::MAX`.
+    pub fn new(channels: u8, size: usize) -> Self {
+        Mix {
+            size,
+            output: vec![0.0; channels as _],
+        }
+    }
+}
+
+impl Module for Mix {
+    fn inputs(&self) -> u8 {
+        self.size as u8
+    }
+
+    fn output(&self) -> &Frame {
+        &self.output
+    }
+
+    fn sample(&mut self, input: &Frame) {
+        if self.size == 0 {
+            return;
+        }
+        for (output, input) in self.output.iter_mut().zip(input.chunks(self.size)) {
+            *output = input.iter().sum();
+        }
+    }
+
+    fn is_pure(&self) -> bool {
+        true
+    }
+
+    fn key(&self) -> Option<Vec<u64>> {
+        Some(Vec::new())
+    }
+}
+
+pub struct Splay {
+    size: usize,
+    output: Vec<Sample>,
+}
+
+impl Splay {
+    /// Splay of `size` inputs, which must not exceed `u8::MAX`.
+    pub fn new(channels: u8, size: usize) -> Self {
+        Splay {
+            size,
+            output: vec![0.0; channels as _],
+        }
+    }
+
+    /// Pan position of the input.
+    fn position(&self, index: usize) -> Sample {
+        if self.size > 1 {
+            2.0 * index as Sample / (self.size - 1) as Sample - 1.0
+        } else {
+            0.0
+        }
+    }
+}
+
+impl Module for Splay {
+    fn inputs(&self) -> u8 {
+        self.size as u8
+    }
+
+    fn output(&self) -> &Frame {
+        &self.output
+    }
+
+    fn sample(&mut self, input: &Frame) {
+        let (mut left, mut right) = (0.0, 0.0);
+        // Inputs of the first channel go first.
+        for (i, x) in input.iter().take(self.size).enumerate() {
+            let (l, r) = pure::pan(*x, *x, self.position(i));
+            left += l;
+            right += r;
+        }
+        let level = (self.size.max(1) as Sample).sqrt().recip();
+        for (channel, output) in self.output.iter_mut().enumerate() {
+            *output = match channel {
+                0 => left * level,
+                1 => right * level,
+                _ => 0.0,
+            };
+        }
+    }
+
+    fn is_pure(&self) -> bool {
+        true
+    }
+
+    fn key(&self) -> Option<Vec<u64>> {
+        Some(Vec::new())
+    }
+}
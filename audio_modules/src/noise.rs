@@ -2,19 +2,25 @@
 //!
 //! White noise.
 //!
+//! Output is fully determined by the seed, so renders are reproducible. Random numbers come from
+//! PCG (`Pcg64Mcg`), which unlike `SmallRng` is the same on every platform and `rand` release.
+//!
 //! Sources to connect: none required.
 use audio_graph::{Frame, Module, Sample};
-use rand::{rngs::SmallRng, Rng, SeedableRng};
+use rand::{Rng, SeedableRng};
+use rand_pcg::Pcg64Mcg;
 
 pub struct WhiteNoise {
-    rng: SmallRng,
+    seed: u64,
+    rng: Pcg64Mcg,
     output: Vec<Sample>,
 }
 
 impl WhiteNoise {
-    pub fn new(channels: u8) -> Self {
+    pub fn new(channels: u8, seed: u64) -> Self {
         WhiteNoise {
-            rng: SmallRng::from_entropy(),
+            seed,
+            rng: Pcg64Mcg::seed_from_u64(seed),
             output: vec![0.0; channels as _],
         }
     }
@@ -34,4 +40,10 @@ impl Module for WhiteNoise {
             *sample = self.rng.gen_range(-1.0, 1.0);
         }
     }
+
+    /// Restart the random stream from the seed.
+    fn reset(&mut self) {
+        self.rng = Pcg64Mcg::seed_from_u64(self.seed);
+        self.output.iter_mut().for_each(|x| *x = 0.0);
+    }
 }
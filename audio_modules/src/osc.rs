@@ -23,6 +23,10 @@ impl Module for Osc {
         1
     }
 
+    fn ports(&self) -> &'static [&'static str] {
+        &["frequency"]
+    }
+
     fn output(&self) -> &Frame {
         self.osc.output()
     }
@@ -31,6 +35,20 @@ impl Module for Osc {
         self.phasor.sample(input);
         self.osc.sample(self.phasor.output());
     }
+
+    fn migrate(&mut self, old: &dyn Module) {
+        if let Some(old) = old.as_any().downcast_ref::<Self>() {
+            self.phasor.migrate(&old.phasor);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.phasor.reset();
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.phasor.set_sample_rate(sample_rate);
+    }
 }
 
 pub struct OscPhase {
@@ -51,6 +69,10 @@ impl Module for OscPhase {
         2
     }
 
+    fn ports(&self) -> &'static [&'static str] {
+        &["frequency", "phase"]
+    }
+
     fn output(&self) -> &Frame {
         self.osc.output()
     }
@@ -59,4 +81,18 @@ impl Module for OscPhase {
         self.phasor.sample(input);
         self.osc.sample(self.phasor.output());
     }
+
+    fn migrate(&mut self, old: &dyn Module) {
+        if let Some(old) = old.as_any().downcast_ref::<Self>() {
+            self.phasor.migrate(&old.phasor);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.phasor.reset();
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.phasor.set_sample_rate(sample_rate);
+    }
 }
@@ -21,6 +21,10 @@ impl Module for Pan1 {
         2
     }
 
+    fn ports(&self) -> &'static [&'static str] {
+        &["input", "position"]
+    }
+
     fn output(&self) -> &Frame {
         &self.output
     }
@@ -30,6 +34,14 @@ impl Module for Pan1 {
         self.output[0] = l;
         self.output[1] = r;
     }
+
+    fn is_pure(&self) -> bool {
+        true
+    }
+
+    fn key(&self) -> Option<Vec<u64>> {
+        Some(Vec::new())
+    }
 }
 
 pub struct Pan2 {
@@ -49,6 +61,10 @@ impl Module for Pan2 {
         3
     }
 
+    fn ports(&self) -> &'static [&'static str] {
+        &["left", "right", "position"]
+    }
+
     fn output(&self) -> &Frame {
         &self.output
     }
@@ -61,6 +77,14 @@ impl Module for Pan2 {
         self.output[0] = l;
         self.output[1] = r;
     }
+
+    fn is_pure(&self) -> bool {
+        true
+    }
+
+    fn key(&self) -> Option<Vec<u64>> {
+        Some(Vec::new())
+    }
 }
 
 pub struct Pan3 {
@@ -80,6 +104,10 @@ impl Module for Pan3 {
         3
     }
 
+    fn ports(&self) -> &'static [&'static str] {
+        &["left", "right", "position"]
+    }
+
     fn output(&self) -> &Frame {
         &self.output
     }
@@ -98,4 +126,12 @@ impl Module for Pan3 {
             }
         }
     }
+
+    fn is_pure(&self) -> bool {
+        true
+    }
+
+    fn key(&self) -> Option<Vec<u64>> {
+        Some(Vec::new())
+    }
 }
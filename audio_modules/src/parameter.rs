@@ -36,4 +36,8 @@ impl Module for Parameter {
             *output = value;
         }
     }
+
+    fn key(&self) -> Option<Vec<u64>> {
+        Some(vec![self.index as u64])
+    }
 }
@@ -1,6 +1,6 @@
 //! # Phasor
 //!
-//! ```
+//! ```text
 //!  1     /|    /|    /|    /|
 //!       / |   / |   / |   / |
 //!  0   /  |  /  |  /  |  /  |
@@ -16,6 +16,7 @@
 //! themselves anymore.
 //!
 //! Sources to connect: frequency.
+use crate::state;
 use audio_graph::{Frame, Module, Sample};
 
 pub struct Phasor {
@@ -37,6 +38,10 @@ impl Module for Phasor {
         1
     }
 
+    fn ports(&self) -> &'static [&'static str] {
+        &["frequency"]
+    }
+
     fn output(&self) -> &Frame {
         &self.phases
     }
@@ -44,9 +49,23 @@ impl Module for Phasor {
     fn sample(&mut self, input: &Frame) {
         for (phase, frequency) in self.phases.iter_mut().zip(input) {
             let dx = frequency * self.sample_period;
-            *phase = ((*phase + dx + 1.0) % 2.0) - 1.0;;
+            *phase = ((*phase + dx + 1.0) % 2.0) - 1.0;
+        }
+    }
+
+    fn migrate(&mut self, old: &dyn Module) {
+        if let Some(old) = old.as_any().downcast_ref::<Self>() {
+            state::copy(&mut self.phases, &old.phases);
         }
     }
+
+    fn reset(&mut self) {
+        state::clear(&mut self.phases);
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_period = Sample::from(sample_rate).recip();
+    }
 }
 
 pub struct Phasor0 {
@@ -68,6 +87,10 @@ impl Module for Phasor0 {
         2
     }
 
+    fn ports(&self) -> &'static [&'static str] {
+        &["frequency", "phase"]
+    }
+
     fn output(&self) -> &Frame {
         &self.phases
     }
@@ -80,4 +103,18 @@ impl Module for Phasor0 {
             *phase = ((*phase + phase0 + dx + 1.0) % 2.0) - 1.0;
         }
     }
+
+    fn migrate(&mut self, old: &dyn Module) {
+        if let Some(old) = old.as_any().downcast_ref::<Self>() {
+            state::copy(&mut self.phases, &old.phases);
+        }
+    }
+
+    fn reset(&mut self) {
+        state::clear(&mut self.phases);
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_period = Sample::from(sample_rate).recip();
+    }
 }
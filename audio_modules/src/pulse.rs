@@ -26,6 +26,10 @@ impl Module for Pulse {
         2
     }
 
+    fn ports(&self) -> &'static [&'static str] {
+        &["frequency", "width"]
+    }
+
     fn output(&self) -> &Frame {
         self.osc.output()
     }
@@ -42,4 +46,18 @@ impl Module for Pulse {
         }
         self.osc.sample(&self.input);
     }
+
+    fn migrate(&mut self, old: &dyn Module) {
+        if let Some(old) = old.as_any().downcast_ref::<Self>() {
+            self.phasor.migrate(&old.phasor);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.phasor.reset();
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.phasor.set_sample_rate(sample_rate);
+    }
 }
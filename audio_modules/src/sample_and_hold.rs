@@ -1,6 +1,7 @@
 //! Sample & Hold
 //!
 //! Sources to connect: trigger, input.
+use crate::state;
 use audio_graph::{Frame, Module, Sample};
 
 pub struct SampleAndHold {
@@ -20,6 +21,10 @@ impl Module for SampleAndHold {
         2
     }
 
+    fn ports(&self) -> &'static [&'static str] {
+        &["trigger", "input"]
+    }
+
     fn output(&self) -> &Frame {
         &self.output
     }
@@ -31,4 +36,14 @@ impl Module for SampleAndHold {
             *output = *output * (1.0 - t) + x * t
         }
     }
+
+    fn migrate(&mut self, old: &dyn Module) {
+        if let Some(old) = old.as_any().downcast_ref::<Self>() {
+            state::copy(&mut self.output, &old.output);
+        }
+    }
+
+    fn reset(&mut self) {
+        state::clear(&mut self.output);
+    }
 }
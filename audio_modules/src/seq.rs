@@ -0,0 +1,106 @@
+//! # Step sequencer
+//!
+//! Sequencer outputs one of its values and steps to another one on each trigger, i.e. when
+//! trigger input rises above zero.
+//!
+//! Sources to connect: values, trigger.
+use crate::state;
+use audio_graph::{Frame, Module, Sample};
+use rand::{Rng, SeedableRng};
+use rand_pcg::Pcg64Mcg;
+
+/// Order in which sequencer steps through values.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Traversal {
+    /// From the first value to the last one and again.
+    Forward,
+    /// Random value each step.
+    Random,
+    /// From the first value to the last one and back, not repeating the ends.
+    PingPong,
+}
+
+pub struct Seq {
+    size: usize,
+    traversal: Traversal,
+    seed: u64,
+    rng: Pcg64Mcg,
+    /// Index of the current value and the direction of the next step in each channel.
+    positions: Vec<(usize, isize)>,
+    /// Trigger input of the previous frame in each channel.
+    triggers: Vec<Sample>,
+    output: Vec<Sample>,
+}
+
+impl Seq {
+    /// Sequencer of `size` values, which must be less than `u8::MAX` to fit the trigger input
+    /// too.
+    pub fn new(channels: u8, size: usize, traversal: Traversal, seed: u64) -> Self {
+        Seq {
+            size,
+            traversal,
+            seed,
+            rng: Pcg64Mcg::seed_from_u64(seed),
+            positions: vec![(0, 1); channels as _],
+            triggers: vec![0.0; channels as _],
+            output: vec![0.0; channels as _],
+        }
+    }
+
+    fn step(&mut self, channel: usize) {
+        let (position, direction) = &mut self.positions[channel];
+        match self.traversal {
+            Traversal::Forward => *position = (*position + 1) % self.size,
+            Traversal::Random => *position = self.rng.gen_range(0, self.size),
+            Traversal::PingPong if self.size > 1 => {
+                if (*position == 0 && *direction < 0) || *position + 1 == self.size {
+                    *direction = -*direction;
+                }
+                *position = (*position as isize + *direction) as usize;
+            }
+            Traversal::PingPong => {}
+        }
+    }
+}
+
+impl Module for Seq {
+    fn inputs(&self) -> u8 {
+        (self.size + 1) as u8
+    }
+
+    fn output(&self) -> &Frame {
+        &self.output
+    }
+
+    fn sample(&mut self, input: &Frame) {
+        if self.size == 0 {
+            return;
+        }
+        for (channel, input) in input.chunks(self.size + 1).enumerate() {
+            let trigger = input[self.size];
+            if trigger > 0.0 && self.triggers[channel] <= 0.0 {
+                self.step(channel);
+            }
+            self.triggers[channel] = trigger;
+            self.output[channel] = input[self.positions[channel].0];
+        }
+    }
+
+    fn migrate(&mut self, old: &dyn Module) {
+        if let Some(old) = old.as_any().downcast_ref::<Self>() {
+            // Keep the position only in the sequence of the same length, otherwise start over.
+            if old.size == self.size {
+                state::copy(&mut self.positions, &old.positions);
+            }
+            state::copy(&mut self.triggers, &old.triggers);
+            state::copy(&mut self.output, &old.output);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.rng = Pcg64Mcg::seed_from_u64(self.seed);
+        self.positions.iter_mut().for_each(|x| *x = (0, 1));
+        state::clear(&mut self.triggers);
+        state::clear(&mut self.output);
+    }
+}
@@ -5,6 +5,7 @@
 //! Source to connect: input.
 //!
 //! TODO: make a generic do-something-with-spectrum module for easier experiments.
+use crate::state;
 use audio_graph::{Frame, Module, Sample};
 use rustfft::algorithm::Radix4;
 use rustfft::num_complex::Complex;
@@ -12,6 +13,9 @@ use rustfft::num_traits::Zero;
 use rustfft::FFT;
 use std::collections::VecDeque;
 
+/// Transformation applied to the spectrum of every window.
+pub type Transform = Box<dyn FnMut(&mut Vec<Complex<Sample>>) + Send>;
+
 pub struct SpectralTransform {
     input_buffers: Vec<VecDeque<Complex<Sample>>>,
     input_scratch: Vec<Complex<Sample>>,
@@ -25,18 +29,13 @@ pub struct SpectralTransform {
     period_offset: usize,
     window: Vec<Complex<Sample>>,
     frame_number: usize,
-    transform: Box<dyn FnMut(&mut Vec<Complex<Sample>>) + Send>,
+    transform: Transform,
 }
 
 impl SpectralTransform {
     // window_size = 2048
     // period = 64
-    pub fn new(
-        channels: u8,
-        window_size: usize,
-        period: usize,
-        transform: Box<dyn FnMut(&mut Vec<Complex<Sample>>) + Send>,
-    ) -> Self {
+    pub fn new(channels: u8, window_size: usize, period: usize, transform: Transform) -> Self {
         let channels = channels as usize;
         let mut input_buffers = Vec::with_capacity(channels);
         let mut output_buffers = Vec::with_capacity(channels);
@@ -75,6 +74,10 @@ impl Module for SpectralTransform {
         1
     }
 
+    fn ports(&self) -> &'static [&'static str] {
+        &["input"]
+    }
+
     fn output(&self) -> &Frame {
         &self.output
     }
@@ -88,7 +91,7 @@ impl Module for SpectralTransform {
             self.output_buffers.iter_mut()
         ) {
             if index == 0 {
-                let mut scratch = &mut self.input_scratch;
+                let scratch = &mut self.input_scratch;
                 let freq_buffer = &mut self.freq_buffer;
                 let input_slices = input_buffer.as_slices();
                 let n = input_slices.0.len();
@@ -97,7 +100,7 @@ impl Module for SpectralTransform {
                 for (x, a) in scratch.iter_mut().zip(&self.window) {
                     *x *= a;
                 }
-                self.fft.process(&mut scratch, freq_buffer);
+                self.fft.process(scratch, freq_buffer);
                 (self.transform)(freq_buffer);
                 self.ifft.process(freq_buffer, output_buffer);
             }
@@ -107,4 +110,31 @@ impl Module for SpectralTransform {
         }
         self.frame_number += 1;
     }
+
+    fn migrate(&mut self, old: &dyn Module) {
+        if let Some(old) = old.as_any().downcast_ref::<Self>() {
+            if self.period_mask == old.period_mask {
+                for (buffer, old_buffer) in self.input_buffers.iter_mut().zip(&old.input_buffers) {
+                    state::copy(buffer, old_buffer);
+                }
+                for (buffer, old_buffer) in self.output_buffers.iter_mut().zip(&old.output_buffers)
+                {
+                    state::copy(buffer, old_buffer);
+                }
+                state::copy(&mut self.output, &old.output);
+                self.frame_number = old.frame_number;
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        for buffer in self.input_buffers.iter_mut() {
+            state::clear(buffer);
+        }
+        for buffer in self.output_buffers.iter_mut() {
+            state::clear(buffer);
+        }
+        state::clear(&mut self.output);
+        self.frame_number = 0;
+    }
 }
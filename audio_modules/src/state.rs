@@ -0,0 +1,22 @@
+//! # State
+//!
+//! Helpers for `Module::migrate` and `Module::reset` implementations.
+
+/// Copy `src` state into `dst` element by element.
+/// When lengths differ (e.g. channels count or maximum delay was changed) the common prefix is
+/// copied and the rest of `dst` is left intact.
+pub(crate) fn copy<'a, T: Copy + 'a>(
+    dst: impl IntoIterator<Item = &'a mut T>,
+    src: impl IntoIterator<Item = &'a T>,
+) {
+    for (x, y) in dst.into_iter().zip(src) {
+        *x = *y;
+    }
+}
+
+/// Reset `dst` state to the default (zero) value element by element.
+pub(crate) fn clear<'a, T: Default + 'a>(dst: impl IntoIterator<Item = &'a mut T>) {
+    for x in dst {
+        *x = T::default();
+    }
+}
@@ -5,6 +5,7 @@
 //! Sources to connect: signal to detect pitch of.
 //!
 //! TODO use FFT and avoid O(n^2)
+use crate::state;
 use audio_graph::{Frame, Module, Sample};
 use std::collections::VecDeque;
 
@@ -76,7 +77,7 @@ impl Yin {
         // The first two positions in yinBuffer are always so start at the third (index 2).
         let mut tau = 2;
         let buffer_len = self.buffer.len();
-        while tau < buffer_len && !(self.buffer[tau] < self.threshold) {
+        while tau < buffer_len && self.buffer[tau] >= self.threshold {
             tau += 1;
         }
         while tau + 1 < buffer_len && self.buffer[tau + 1] < self.buffer[tau] {
@@ -112,6 +113,10 @@ impl Module for Yin {
         1
     }
 
+    fn ports(&self) -> &'static [&'static str] {
+        &["input"]
+    }
+
     fn output(&self) -> &Frame {
         &self.output
     }
@@ -121,7 +126,7 @@ impl Module for Yin {
             window.pop_front();
             window.push_back(*input);
         }
-        if self.frame_number % self.period == 0 {
+        if self.frame_number.is_multiple_of(self.period) {
             for channel in 0..self.channels {
                 self.difference(channel);
                 self.cumulative_mean_normalized_difference();
@@ -135,4 +140,26 @@ impl Module for Yin {
         }
         self.frame_number += 1;
     }
+
+    fn migrate(&mut self, old: &dyn Module) {
+        if let Some(old) = old.as_any().downcast_ref::<Self>() {
+            for (window, old_window) in self.windows.iter_mut().zip(&old.windows) {
+                state::copy(window, old_window);
+            }
+            state::copy(&mut self.output, &old.output);
+            self.frame_number = old.frame_number;
+        }
+    }
+
+    fn reset(&mut self) {
+        for window in self.windows.iter_mut() {
+            state::clear(window);
+        }
+        state::clear(&mut self.output);
+        self.frame_number = 0;
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = Sample::from(sample_rate);
+    }
 }
@@ -0,0 +1,183 @@
+//! # Test harness
+//!
+//! Helpers to render modules with deterministic inputs, compare the result with reference
+//! buffers stored in `tests/golden` and inspect its spectrum.
+//!
+//! Run tests with `UPDATE_GOLDEN=1` to (re)generate reference buffers after an intentional change
+//! of the sound, and review the diff of `tests/golden` before commit.
+#![allow(dead_code)]
+
+use audio_graph::{Module, Sample};
+use rustfft::num_complex::Complex;
+use rustfft::num_traits::Zero;
+use rustfft::FFTplanner;
+use std::f64::consts::PI;
+use std::path::PathBuf;
+
+pub const SAMPLE_RATE: u32 = 48_000;
+
+/// Maximum absolute difference between a rendered sample and a reference one.
+pub const TOLERANCE: Sample = 1e-9;
+
+/// Render `frames` frames of a single-channel `module`.
+///
+/// `inputs` are signals connected to the module's ports, in the order of ports. They are
+/// interleaved into the module's input layout and passed to `Module::process` at once.
+pub fn render(module: &mut dyn Module, inputs: &[Vec<Sample>], frames: usize) -> Vec<Sample> {
+    assert_eq!(
+        inputs.len(),
+        module.inputs() as usize,
+        "wrong number of inputs"
+    );
+    let mut input = Vec::with_capacity(frames * inputs.len());
+    for i in 0..frames {
+        input.extend(inputs.iter().map(|signal| signal[i]));
+    }
+    let mut output = vec![0.0; frames];
+    module.process(&input, &mut output, frames);
+    output
+}
+
+/// Signal which is always `x`.
+pub fn constant(x: Sample, frames: usize) -> Vec<Sample> {
+    vec![x; frames]
+}
+
+/// Sine wave of the `frequency` in Hz.
+pub fn sine(frequency: Sample, frames: usize) -> Vec<Sample> {
+    (0..frames)
+        .map(|i| (2.0 * PI * frequency * i as Sample / Sample::from(SAMPLE_RATE)).sin())
+        .collect()
+}
+
+/// Single 1.0 at the first frame followed by silence.
+pub fn impulse(frames: usize) -> Vec<Sample> {
+    (0..frames)
+        .map(|i| if i == 0 { 1.0 } else { 0.0 })
+        .collect()
+}
+
+/// 1.0 every `period` frames, 0.0 otherwise.
+pub fn impulses(period: usize, frames: usize) -> Vec<Sample> {
+    (0..frames)
+        .map(|i| if i % period == 0 { 1.0 } else { 0.0 })
+        .collect()
+}
+
+/// White noise in the range -1..1 which is the same for the same `seed`.
+pub fn noise(seed: u64, frames: usize) -> Vec<Sample> {
+    // xorshift64*, simple and stable across rand versions
+    let mut state = seed.max(1);
+    (0..frames)
+        .map(|_| {
+            state ^= state >> 12;
+            state ^= state << 25;
+            state ^= state >> 27;
+            let x = state.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 11;
+            2.0 * (x as Sample / (1u64 << 53) as Sample) - 1.0
+        })
+        .collect()
+}
+
+/// Compare `signal` with the reference buffer `tests/golden/<name>.txt`.
+///
+/// With `UPDATE_GOLDEN` environment variable set reference buffer is overwritten instead.
+pub fn assert_golden(name: &str, signal: &[Sample]) {
+    let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    path.push("tests");
+    path.push("golden");
+    path.push(format!("{}.txt", name));
+
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        let text = signal
+            .iter()
+            .map(|x| format!("{:e}\n", x))
+            .collect::<String>();
+        std::fs::write(&path, text).expect("Failed to write reference buffer");
+        return;
+    }
+
+    let text = std::fs::read_to_string(&path).unwrap_or_else(|_| {
+        panic!(
+            "No reference buffer {}, run with UPDATE_GOLDEN=1 to create it",
+            path.display()
+        )
+    });
+    let reference = text
+        .lines()
+        .map(|line| line.parse::<Sample>().expect("Malformed reference buffer"))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        signal.len(),
+        reference.len(),
+        "{}: length differs from the reference",
+        name
+    );
+    for (i, (x, y)) in signal.iter().zip(&reference).enumerate() {
+        assert!(
+            (x - y).abs() <= TOLERANCE || (x.is_nan() && y.is_nan()),
+            "{}: frame {} is {} but reference is {}",
+            name,
+            i,
+            x,
+            y
+        );
+    }
+}
+
+/// Magnitudes of the Hann-windowed `signal` spectrum from 0 up to the Nyquist frequency.
+/// Bin `i` corresponds to the frequency `i * SAMPLE_RATE / signal.len()`.
+pub fn spectrum(signal: &[Sample]) -> Vec<Sample> {
+    let n = signal.len();
+    let mut input = signal
+        .iter()
+        .zip(apodize::hanning_iter(n))
+        .map(|(x, a)| Complex::new(x * a, 0.0))
+        .collect::<Vec<_>>();
+    let mut output = vec![Complex::zero(); n];
+    FFTplanner::new(false)
+        .plan_fft(n)
+        .process(&mut input, &mut output);
+    output[..n / 2 + 1].iter().map(|x| x.norm()).collect()
+}
+
+/// Frequency of the bin with the highest magnitude.
+pub fn peak_frequency(signal: &[Sample]) -> Sample {
+    let (bin, _) = spectrum(signal)
+        .into_iter()
+        .enumerate()
+        .fold(
+            (0, 0.0),
+            |(i, a), (j, b)| if b > a { (j, b) } else { (i, a) },
+        );
+    bin_frequency(bin, signal.len())
+}
+
+/// Sum of squared magnitudes of bins in the frequency range `low..high` Hz.
+pub fn band_energy(signal: &[Sample], low: Sample, high: Sample) -> Sample {
+    spectrum(signal)
+        .into_iter()
+        .enumerate()
+        .filter(|(bin, _)| {
+            let frequency = bin_frequency(*bin, signal.len());
+            low <= frequency && frequency < high
+        })
+        .map(|(_, x)| x * x)
+        .sum()
+}
+
+/// Assert that the strongest frequency of `signal` is `frequency` up to the bin width.
+pub fn assert_peak(signal: &[Sample], frequency: Sample) {
+    let peak = peak_frequency(signal);
+    let bin_width = Sample::from(SAMPLE_RATE) / signal.len() as Sample;
+    assert!(
+        (peak - frequency).abs() <= bin_width,
+        "peak is at {} Hz but expected {} Hz",
+        peak,
+        frequency
+    );
+}
+
+fn bin_frequency(bin: usize, len: usize) -> Sample {
+    bin as Sample * Sample::from(SAMPLE_RATE) / len as Sample
+}
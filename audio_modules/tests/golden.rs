@@ -0,0 +1,217 @@
+//! Compare modules' output with reference buffers, ref `common` for details.
+mod common;
+
+use audio_graph::Module;
+use audio_modules::*;
+use common::*;
+
+const FRAMES: usize = 1024;
+
+#[test]
+fn phasor() {
+    let mut module = Phasor::new(1, SAMPLE_RATE);
+    let output = render(&mut module, &[constant(440.0, FRAMES)], FRAMES);
+    assert_golden("phasor", &output);
+}
+
+#[test]
+fn phasor0() {
+    let mut module = Phasor0::new(1, SAMPLE_RATE);
+    let inputs = [constant(440.0, FRAMES), sine(3.0, FRAMES)];
+    let output = render(&mut module, &inputs, FRAMES);
+    assert_golden("phasor0", &output);
+}
+
+#[test]
+fn osc() {
+    let mut module = Osc::new(1, SAMPLE_RATE, pure::sine);
+    let output = render(&mut module, &[constant(440.0, FRAMES)], FRAMES);
+    assert_golden("osc", &output);
+}
+
+#[test]
+fn osc_phase() {
+    let mut module = OscPhase::new(1, SAMPLE_RATE, pure::triangle);
+    let inputs = [constant(440.0, FRAMES), constant(0.25, FRAMES)];
+    let output = render(&mut module, &inputs, FRAMES);
+    assert_golden("osc_phase", &output);
+}
+
+#[test]
+fn pulse() {
+    let mut module = Pulse::new(1, SAMPLE_RATE);
+    let inputs = [constant(440.0, FRAMES), constant(0.25, FRAMES)];
+    let output = render(&mut module, &inputs, FRAMES);
+    assert_golden("pulse", &output);
+}
+
+#[test]
+fn lpf() {
+    let mut module = LPF::new(1, SAMPLE_RATE);
+    let inputs = [noise(1, FRAMES), constant(1000.0, FRAMES)];
+    let output = render(&mut module, &inputs, FRAMES);
+    assert_golden("lpf", &output);
+}
+
+#[test]
+fn hpf() {
+    let mut module = HPF::new(1, SAMPLE_RATE);
+    let inputs = [noise(1, FRAMES), constant(1000.0, FRAMES)];
+    let output = render(&mut module, &inputs, FRAMES);
+    assert_golden("hpf", &output);
+}
+
+#[test]
+fn biquad_lpf() {
+    let mut module = BiQuad::new(1, SAMPLE_RATE, make_lpf_coefficients);
+    let inputs = [
+        noise(1, FRAMES),
+        constant(1000.0, FRAMES),
+        constant(0.7, FRAMES),
+    ];
+    let output = render(&mut module, &inputs, FRAMES);
+    assert_golden("biquad_lpf", &output);
+}
+
+#[test]
+fn biquad_hpf() {
+    let mut module = BiQuad::new(1, SAMPLE_RATE, make_hpf_coefficients);
+    let inputs = [
+        noise(1, FRAMES),
+        constant(1000.0, FRAMES),
+        constant(0.7, FRAMES),
+    ];
+    let output = render(&mut module, &inputs, FRAMES);
+    assert_golden("biquad_hpf", &output);
+}
+
+#[test]
+fn delay() {
+    let mut module = Delay::new(1, SAMPLE_RATE, 0.01);
+    // Delay time between 1ms and 2ms exercises interpolation.
+    let time = sine(50.0, FRAMES)
+        .into_iter()
+        .map(|x| 0.0015 + 0.0005 * x)
+        .collect();
+    let output = render(&mut module, &[noise(1, FRAMES), time], FRAMES);
+    assert_golden("delay", &output);
+}
+
+#[test]
+fn feedback() {
+    let mut module = Feedback::new(1, SAMPLE_RATE, 0.01);
+    let inputs = [
+        impulse(FRAMES),
+        constant(0.001, FRAMES),
+        constant(0.5, FRAMES),
+    ];
+    let output = render(&mut module, &inputs, FRAMES);
+    assert_golden("feedback", &output);
+}
+
+#[test]
+fn metro() {
+    let mut module = Metro::new(1, SAMPLE_RATE);
+    let output = render(&mut module, &[constant(200.0, FRAMES)], FRAMES);
+    assert_golden("metro", &output);
+}
+
+#[test]
+fn dmetro() {
+    let mut module = DMetro::new(1, SAMPLE_RATE);
+    let output = render(&mut module, &[constant(0.005, FRAMES)], FRAMES);
+    assert_golden("dmetro", &output);
+}
+
+#[test]
+fn metro_hold() {
+    let mut module = MetroHold::new(1, SAMPLE_RATE);
+    let frequency = (0..FRAMES)
+        .map(|i| if i < FRAMES / 2 { 200.0 } else { 400.0 })
+        .collect();
+    let output = render(&mut module, &[frequency], FRAMES);
+    assert_golden("metro_hold", &output);
+}
+
+#[test]
+fn impulse_envelope() {
+    let mut module = envelopes::Impulse::new(1, SAMPLE_RATE);
+    let inputs = [impulses(256, FRAMES), constant(0.001, FRAMES)];
+    let output = render(&mut module, &inputs, FRAMES);
+    assert_golden("impulse", &output);
+    module.reset();
+    assert_eq!(render(&mut module, &inputs, FRAMES), output);
+    // The envelope takes the same time at the doubled sample rate.
+    module.reset();
+    module.set_sample_rate(2 * SAMPLE_RATE);
+    let inputs = [impulses(512, 2 * FRAMES), constant(0.001, 2 * FRAMES)];
+    let doubled = render(&mut module, &inputs, 2 * FRAMES);
+    for (x, y) in output.iter().zip(doubled.iter().step_by(2)) {
+        assert!((x - y).abs() < TOLERANCE);
+    }
+}
+
+#[test]
+fn sample_and_hold() {
+    let mut module = SampleAndHold::new(1);
+    let inputs = [impulses(64, FRAMES), noise(1, FRAMES)];
+    let output = render(&mut module, &inputs, FRAMES);
+    assert_golden("sample_and_hold", &output);
+}
+
+#[test]
+fn convolution() {
+    let mut module = Convolution::new(1, 3);
+    let inputs = [noise(1, FRAMES), sine(440.0, FRAMES)];
+    let output = render(&mut module, &inputs, FRAMES);
+    assert_golden("convolution", &output);
+}
+
+#[test]
+fn convolution_m() {
+    let mut module = ConvolutionM::new(1, 3);
+    let inputs = [
+        noise(1, FRAMES),
+        constant(0.25, FRAMES),
+        constant(0.5, FRAMES),
+        constant(0.25, FRAMES),
+    ];
+    let output = render(&mut module, &inputs, FRAMES);
+    assert_golden("convolution_m", &output);
+}
+
+#[test]
+fn spectral_transform() {
+    // Drop upper half of the spectrum.
+    let mut module = SpectralTransform::new(
+        1,
+        256,
+        64,
+        Box::new(|freqs| {
+            let n = freqs.len();
+            for x in &mut freqs[n / 4..3 * n / 4] {
+                *x = 0.0.into();
+            }
+        }),
+    );
+    let output = render(&mut module, &[noise(1, FRAMES)], FRAMES);
+    assert_golden("spectral_transform", &output);
+}
+
+#[test]
+fn yin() {
+    let frames = 4 * FRAMES;
+    let mut module = Yin::new(1, SAMPLE_RATE, 1024, 64, 0.2);
+    let output = render(&mut module, &[sine(440.0, frames)], frames);
+    assert!((output[frames - 1] - 440.0).abs() < 1.0);
+    assert_golden("yin", &output);
+}
+
+#[test]
+fn white_noise() {
+    let mut module = WhiteNoise::new(1, 42);
+    let output = render(&mut module, &[], FRAMES);
+    assert_golden("white_noise", &output);
+    module.reset();
+    assert_eq!(render(&mut module, &[], FRAMES), output);
+}
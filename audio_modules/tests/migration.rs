@@ -0,0 +1,68 @@
+//! Check that state migrated in parts while the old module keeps running is the same as state
+//! migrated at once, ref `Module::migrate_part`.
+mod common;
+
+use audio_graph::Module;
+use audio_modules::*;
+use common::*;
+
+const FRAMES: usize = 1024;
+const MAX_DELAY: f64 = 0.01;
+
+/// Migrate `old` into `parts` in parts of `part` samples, rendering `frames` frames of `old`
+/// between them, and into `at_once` in the end. Then check that both render the same.
+fn assert_migrated_in_parts(
+    old: &mut dyn Module,
+    parts: &mut dyn Module,
+    at_once: &mut dyn Module,
+    part: usize,
+    frames: usize,
+) {
+    let count = old.inputs() as usize;
+    // Delay time close to the maximum reads the oldest part of delay lines.
+    let inputs = |seed| {
+        let mut inputs = vec![noise(seed, FRAMES), constant(0.0095, FRAMES)];
+        inputs.resize(count, constant(0.5, FRAMES));
+        inputs
+    };
+    render(old, &inputs(1), FRAMES);
+    let size = parts.migration_size();
+    assert!(size > 0);
+    for (i, start) in (0..size).step_by(part).enumerate() {
+        parts.migrate_part(old, start, size.min(start + part));
+        let input = inputs(i as u64 + 2)
+            .into_iter()
+            .map(|signal| signal[..frames].to_vec())
+            .collect::<Vec<_>>();
+        render(old, &input, frames);
+    }
+    parts.migrate(old);
+    at_once.migrate(old);
+    let inputs = inputs(100);
+    assert_eq!(
+        render(parts, &inputs, FRAMES),
+        render(at_once, &inputs, FRAMES)
+    );
+}
+
+#[test]
+fn delay_in_parts() {
+    let new = || Delay::new(1, SAMPLE_RATE, MAX_DELAY);
+    assert_migrated_in_parts(&mut new(), &mut new(), &mut new(), 100, 16);
+    // Old delay moves more than the whole line further while parts are copied.
+    assert_migrated_in_parts(&mut new(), &mut new(), &mut new(), 100, 200);
+}
+
+#[test]
+fn delay_in_parts_to_longer_delay() {
+    let mut longer = Delay::new(1, SAMPLE_RATE, 2.0 * MAX_DELAY);
+    let mut at_once = Delay::new(1, SAMPLE_RATE, 2.0 * MAX_DELAY);
+    let mut old = Delay::new(1, SAMPLE_RATE, MAX_DELAY);
+    assert_migrated_in_parts(&mut old, &mut longer, &mut at_once, 300, 16);
+}
+
+#[test]
+fn feedback_in_parts() {
+    let new = || Feedback::new(1, SAMPLE_RATE, MAX_DELAY);
+    assert_migrated_in_parts(&mut new(), &mut new(), &mut new(), 100, 16);
+}
@@ -0,0 +1,66 @@
+//! Check frequency content of modules' output, ref `common` for details.
+mod common;
+
+use audio_graph::{Module, Sample};
+use audio_modules::*;
+use common::*;
+
+const FRAMES: usize = 8192;
+
+#[test]
+fn osc_frequency() {
+    let mut module = Osc::new(1, SAMPLE_RATE, pure::sine);
+    let output = render(&mut module, &[constant(1000.0, FRAMES)], FRAMES);
+    assert_peak(&output, 1000.0);
+}
+
+#[test]
+fn osc_sample_rate() {
+    let mut module = Osc::new(1, 44_100, pure::sine);
+    module.set_sample_rate(SAMPLE_RATE);
+    let output = render(&mut module, &[constant(1000.0, FRAMES)], FRAMES);
+    assert_peak(&output, 1000.0);
+}
+
+/// Ratio of high to low band energy of the `module` output to the same ratio of its input noise.
+fn tilt(module: &mut dyn Module, inputs: &[Vec<Sample>]) -> Sample {
+    let output = render(module, inputs, FRAMES);
+    let ratio = |x: &[Sample]| band_energy(x, 8000.0, 20000.0) / band_energy(x, 20.0, 200.0);
+    ratio(&output) / ratio(&inputs[0])
+}
+
+#[test]
+fn lpf_attenuates_highs() {
+    let mut module = LPF::new(1, SAMPLE_RATE);
+    let inputs = [noise(1, FRAMES), constant(500.0, FRAMES)];
+    assert!(tilt(&mut module, &inputs) < 0.1);
+}
+
+#[test]
+fn hpf_attenuates_lows() {
+    let mut module = HPF::new(1, SAMPLE_RATE);
+    let inputs = [noise(1, FRAMES), constant(2000.0, FRAMES)];
+    assert!(tilt(&mut module, &inputs) > 10.0);
+}
+
+#[test]
+fn biquad_lpf_attenuates_highs() {
+    let mut module = BiQuad::new(1, SAMPLE_RATE, make_lpf_coefficients);
+    let inputs = [
+        noise(1, FRAMES),
+        constant(500.0, FRAMES),
+        constant(0.7, FRAMES),
+    ];
+    assert!(tilt(&mut module, &inputs) < 0.01);
+}
+
+#[test]
+fn biquad_hpf_attenuates_lows() {
+    let mut module = BiQuad::new(1, SAMPLE_RATE, make_hpf_coefficients);
+    let inputs = [
+        noise(1, FRAMES),
+        constant(2000.0, FRAMES),
+        constant(0.7, FRAMES),
+    ];
+    assert!(tilt(&mut module, &inputs) > 100.0);
+}
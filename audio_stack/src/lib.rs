@@ -1,157 +1,955 @@
-use audio_graph::{stack, AudioGraph, Sample};
-use audio_modules::*;
-use rand::{rngs::SmallRng, seq::SliceRandom, SeedableRng};
+mod literal;
+pub mod registry;
 
-macro_rules! connect {
-    ( $ops:ident, $class:ident, $($rest:tt)* ) => { $ops.push(stack::Op::Connect(Box::new($class::new($($rest)*)))) };
-}
+pub use registry::Registry;
+
+use audio_graph::{stack, AudioGraph, Port, Sample};
+use audio_modules::{Constant, Mix};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
 #[derive(Debug)]
 pub enum ParseError {
     UnknownToken(String),
     NotEnoughParameters(String),
     WrongParameterType(String),
+    /// Module has no parameter with the name, (token, name) pair.
+    UnknownParameter(String, String),
+    /// Token has more positional parameters than module accepts.
+    TooManyParameters(String),
+    /// Word definition is missing `;`.
+    UnterminatedDefinition(String),
+    /// Stack effect comment of the word is not of the form `( inputs -- outputs )`.
+    MalformedStackEffect(String),
+    /// Word's body consumes or produces a different number of items than its stack effect
+    /// comment declares. Effects are (inputs, outputs) pairs.
+    WrongStackEffect {
+        word: String,
+        declared: (usize, usize),
+        actual: (usize, usize),
+    },
+    /// Included file can't be read.
+    IncludeFailed(String, String),
+    /// File includes itself, directly or through other files.
+    IncludeCycle(String),
+    /// Error in the included file, `source` is its content.
+    Included {
+        path: PathBuf,
+        source: String,
+        error: Box<Error>,
+    },
+    /// `}` without `{` or `{` without `}`.
+    UnbalancedQuote,
+    /// Quote is not followed by a combinator.
+    MissingCombinator,
+    /// Count of `repeat` is not a non-negative integer literal right before the quote.
+    MissingRepeatCount(String),
+    /// List or `repeat` has more items than `stack::MAX_LIST_LEN`.
+    ListTooLong(usize),
+    /// Channel index of the token is not less than the number of channels.
+    ChannelOutOfRange {
+        token: String,
+        channels: u8,
+    },
 }
 
 #[derive(Debug)]
 pub enum Error {
-    ParseError(ParseError),
-    StackError(stack::Error),
+    ParseError(Span, ParseError),
+    StackError(Span, stack::Error),
+}
+
+/// Position of a token in the source. Line and column are counted from 1, column and length are
+/// in characters.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+    pub len: usize,
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::UnknownToken(token) => write!(f, "unknown token `{}`", token),
+            ParseError::NotEnoughParameters(token) => {
+                write!(f, "not enough parameters for `{}`", token)
+            }
+            ParseError::WrongParameterType(token) => {
+                write!(f, "wrong parameter type in `{}`", token)
+            }
+            ParseError::UnknownParameter(token, name) => {
+                write!(f, "unknown parameter `{}` in `{}`", name, token)
+            }
+            ParseError::TooManyParameters(token) => {
+                write!(f, "too many parameters for `{}`", token)
+            }
+            ParseError::UnterminatedDefinition(word) => {
+                write!(f, "definition of `{}` is not terminated with `;`", word)
+            }
+            ParseError::MalformedStackEffect(word) => write!(
+                f,
+                "malformed stack effect of `{}`, expected `( inputs -- outputs )`",
+                word
+            ),
+            ParseError::WrongStackEffect {
+                word,
+                declared,
+                actual,
+            } => write!(
+                f,
+                "`{}` is declared as ( {} -- {} ) but its body is ( {} -- {} )",
+                word, declared.0, declared.1, actual.0, actual.1
+            ),
+            ParseError::IncludeFailed(path, reason) => {
+                write!(f, "failed to include `{}`: {}", path, reason)
+            }
+            ParseError::IncludeCycle(path) => write!(f, "`{}` includes itself", path),
+            ParseError::Included { path, error, .. } => {
+                write!(f, "in `{}`: {}", path.display(), error)
+            }
+            ParseError::UnbalancedQuote => write!(f, "unbalanced quote braces"),
+            ParseError::MissingCombinator => write!(
+                f,
+                "quote must be followed by `map`, `map-mix`, `repeat` or `repeat-mix`"
+            ),
+            ParseError::MissingRepeatCount(token) => write!(
+                f,
+                "`{}` needs the count as a number literal before the quote",
+                token
+            ),
+            ParseError::ListTooLong(len) => write!(
+                f,
+                "list of {} items is too long, at most {} are supported",
+                len,
+                stack::MAX_LIST_LEN
+            ),
+            ParseError::ChannelOutOfRange { token, channels } => write!(
+                f,
+                "`{}` is out of range, channels are counted from 0 to {}",
+                token,
+                channels.saturating_sub(1)
+            ),
+        }
+    }
 }
 
-pub fn parse_ops(s: &str, channels: u8, sample_rate: u32) -> Result<Vec<stack::Op>, ParseError> {
-    let mut ops = Vec::new();
-    let s = s.replace(|c| c == '[' || c == ']' || c == ',', " ");
-    for token in s
-        .split_terminator('\n')
-        .flat_map(|s| s.splitn(2, "//").take(1).flat_map(|s| s.split_whitespace()))
-    {
+impl std::error::Error for ParseError {}
+
+impl Error {
+    pub fn span(&self) -> Span {
+        match self {
+            Error::ParseError(span, _) | Error::StackError(span, _) => *span,
+        }
+    }
+
+    /// Render error message together with the `source` line it points to, like this:
+    /// ```text
+    /// error: unknown token `sinn`
+    ///  --> 2:5
+    ///   |
+    /// 2 | 440 sinn 0.5 *
+    ///   |     ^^^^
+    /// ```
+    ///
+    /// Errors in included files are rendered for every file in the include chain.
+    pub fn render(&self, source: &str) -> String {
+        let mut result = snippet("error", &self.message(), self.span(), source);
+        if let Error::ParseError(_, ParseError::Included { source, error, .. }) = self {
+            result.push_str(&error.render(source));
+        }
+        result
+    }
+
+    /// Included files the error is in, from the outermost to the innermost.
+    pub fn included(&self) -> Vec<&Path> {
+        let mut paths = Vec::new();
+        let mut error = self;
+        while let Error::ParseError(_, ParseError::Included { path, error: e, .. }) = error {
+            paths.push(path.as_path());
+            error = e;
+        }
+        paths
+    }
+
+    fn message(&self) -> String {
+        match self {
+            Error::ParseError(_, ParseError::Included { path, .. }) => {
+                format!("in included file `{}`", path.display())
+            }
+            Error::ParseError(_, e) => e.to_string(),
+            Error::StackError(_, e) => e.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::ParseError(span, e) => write!(f, "{}: {}", span, e),
+            Error::StackError(span, e) => write!(f, "{}: {}", span, e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Problem found by `Program::check` which doesn't prevent building the graph.
+#[derive(Debug)]
+pub struct Warning {
+    pub span: Span,
+    pub warning: stack::Warning,
+}
+
+impl Warning {
+    /// Render warning together with the `source` line it points to, ref `Error::render`.
+    pub fn render(&self, source: &str) -> String {
+        snippet("warning", &self.warning.to_string(), self.span, source)
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.span, self.warning)
+    }
+}
+
+/// Connection of the built graph which was delayed by one frame to break a cycle, ref
+/// `AudioGraph::delayed_edges`. It's not a problem, but it changes the sound a bit.
+#[derive(Debug)]
+pub struct DelayedEdge {
+    /// Span of the sink node.
+    pub span: Span,
+    /// Span of the source node.
+    pub source: Span,
+    /// Sink's port the source is connected to.
+    pub port: Port,
+}
+
+impl DelayedEdge {
+    /// Render note together with the `source` line it points to, ref `Error::render`.
+    pub fn render(&self, source: &str) -> String {
+        snippet("note", &self.message(), self.span, source)
+    }
+
+    fn message(&self) -> String {
+        format!(
+            "input {} from {} is delayed by one frame to break a cycle",
+            self.port.0, self.source
+        )
+    }
+}
+
+impl fmt::Display for DelayedEdge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.span, self.message())
+    }
+}
+
+/// Render `message` of `level` with the `source` line `span` points to.
+fn snippet(level: &str, message: &str, span: Span, source: &str) -> String {
+    let mut result = format!("{}: {}\n", level, message);
+    let line = match source.split_terminator('\n').nth(span.line.wrapping_sub(1)) {
+        Some(line) => line.trim_end(),
+        None => {
+            result.push_str(&format!(" --> {}\n", span));
+            return result;
+        }
+    };
+    let number = span.line.to_string();
+    let gutter = " ".repeat(number.len());
+    // Keep tabs to align caret with the token.
+    let indent = line
+        .chars()
+        .take(span.column - 1)
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect::<String>();
+    result.push_str(&format!("{}--> {}\n", gutter, span));
+    result.push_str(&format!("{} |\n", gutter));
+    result.push_str(&format!("{} | {}\n", number, line));
+    result.push_str(&format!(
+        "{} | {}{}\n",
+        gutter,
+        indent,
+        "^".repeat(span.len.max(1))
+    ));
+    result
+}
+
+/// Seeds of random modules, derived from the graph-wide seed.
+#[derive(Clone)]
+pub(crate) struct Seeds {
+    seed: u64,
+    index: u64,
+}
+
+impl Seeds {
+    fn new(seed: u64) -> Self {
+        Seeds { seed, index: 0 }
+    }
+
+    /// Seeds for the `run` of a quote, ref `Quote`.
+    fn run(seed: u64, run: usize) -> Self {
+        let mut seeds = Seeds {
+            seed,
+            index: run as u64,
+        };
+        Seeds::new(seeds.next())
+    }
+
+    /// Seed of the next random module.
+    ///
+    /// Each module gets its own stream (e.g. two noises are not the same signal), and it depends
+    /// only on the graph-wide seed and how many random modules precede the module.
+    fn next(&mut self) -> u64 {
+        self.index += 1;
+        // SplitMix64 finalizer
+        let mut z = self
+            .seed
+            .wrapping_add(self.index.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+/// Parse stack source into ops.
+///
+/// `seed` determines the output of all random modules, so the same source and seed always
+/// produce the same sound. `seed:<n>` token replaces it for the following random modules.
+///
+/// Numbers might have units: `250ms` and `2s` are seconds, `1.5khz` is Hz, `-6db` is linear gain,
+/// notes like `c4` and `f#3` are their frequencies and `3/2` is a ratio.
+///
+/// Besides built-in tokens source might define new words Forth-style:
+/// ```text
+/// : voice ( freq -- sig ) s 0.3 * ;
+/// 440 voice 660 voice +
+/// ```
+/// Word expands into its body wherever it's used, so each use creates new modules. Stack effect
+/// comment is optional, when given it is checked against the body's actual stack effect at
+/// definition time.
+///
+/// `=name` pops the top node into the named binding and `name` pushes the same node again, which
+/// allows to reuse a signal without `dup`/`swap`/`rot` juggling:
+/// ```text
+/// 440 s =carrier
+/// carrier 0.5 * carrier 2 * s +
+/// ```
+///
+/// Brackets make a list of items pushed between them (at most `stack::MAX_LIST_LEN`), for
+/// modules which take lists, e.g. step sequencer which steps through notes on each metronome
+/// tick:
+/// ```text
+/// [c4 eb4 g4 bb4] 4 m seq:pingpong s
+/// ```
+/// Other modules are expanded over lists: a module is created for each item, so the chord below
+/// is three oscillators with different frequencies, which `mix` sums and `splay` spreads across
+/// stereo. Shorter lists wrap around and nodes are connected to each module.
+/// ```text
+/// [c4 e4 g4] [1 1.01] * s 0.3 * splay
+/// ```
+/// `chan:n` takes the `n`-th channel of a node, counting from 0.
+///
+/// Braces quote tokens for a combinator which follows them and runs the quote several times,
+/// each run creates new modules and leaves a node on top, which are collected into a list:
+/// - `list { ... } map` runs the quote for each item of the list with the item pushed;
+/// - `n { ... } repeat` runs the quote `n` times with the run number, counted from 1, pushed.
+///   `n` must be a number literal not greater than `stack::MAX_LIST_LEN`.
+///
+/// `map-mix` and `repeat-mix` mix the list in the end. Each run starts with the same stack, so
+/// items the quote takes from below are shared by runs, e.g. eight detuned sines:
+/// ```text
+/// 110 8 { 0.01 * 1 + * s } repeat-mix
+/// ```
+///
+/// `include:lib.sg` token parses the file `lib.sg` in place, so that words it defines could be
+/// used in the rest of the source. Path is resolved relative to the directory of the file source
+/// is read from, which is `path`, or to the current directory if it's unknown.
+pub fn parse_ops(
+    s: &str,
+    channels: u8,
+    sample_rate: u32,
+    seed: u64,
+    path: Option<&Path>,
+) -> Result<Program, Error> {
+    parse_ops_with(&Registry::builtin(), s, channels, sample_rate, seed, path)
+}
+
+/// Parse stack source into ops as `parse_ops` does, taking modules from `registry`.
+pub fn parse_ops_with(
+    registry: &Registry,
+    s: &str,
+    channels: u8,
+    sample_rate: u32,
+    seed: u64,
+    path: Option<&Path>,
+) -> Result<Program, Error> {
+    let registry = Rc::new(registry.clone());
+    let mut parser = Parser::new(registry, channels, sample_rate, Seeds::new(seed));
+    parser.including = path
+        .and_then(|path| path.canonicalize().ok())
+        .into_iter()
+        .collect();
+    let mut program = Program::default();
+    parser.parse(s, path, None, &mut program)?;
+    Ok(program)
+}
+
+/// Parsed stack source.
+#[derive(Default)]
+pub struct Program {
+    pub ops: Vec<stack::Op>,
+    /// Spans of tokens each op was parsed from. Ops of included files have the span of
+    /// `include:` token.
+    pub spans: Vec<Span>,
+    /// Canonical paths of all included files.
+    pub includes: Vec<PathBuf>,
+}
+
+impl Program {
+    /// Check the program without building the graph, ref `stack::check`.
+    /// Returns warnings and how many items are left on the stack.
+    pub fn check(&self) -> Result<(Vec<Warning>, usize), Error> {
+        let span = |op: usize| self.spans.get(op).cloned().unwrap_or_default();
+        let check = stack::check(&self.ops).map_err(|e| Error::StackError(span(e.op()), e))?;
+        let warnings = check
+            .warnings
+            .into_iter()
+            .map(|warning| Warning {
+                span: span(warning.op()),
+                warning,
+            })
+            .collect();
+        Ok((warnings, check.outputs))
+    }
+
+    pub fn build(self, channels: u8, inputs: u8) -> Result<AudioGraph, Error> {
+        let spans = self.spans;
+        stack::build_graph(self.ops, channels, inputs)
+            .map_err(|e| Error::StackError(spans.get(e.op()).cloned().unwrap_or_default(), e))
+    }
+
+    /// Build the graph as `build` does and also return connections delayed to break cycles.
+    pub fn build_with_delayed_edges(
+        self,
+        channels: u8,
+        inputs: u8,
+    ) -> Result<(AudioGraph, Vec<DelayedEdge>), Error> {
+        let spans = self.spans;
+        let span = |op: usize| spans.get(op).cloned().unwrap_or_default();
+        let (graph, origins) = stack::build_graph_with_origins(self.ops, channels, inputs)
+            .map_err(|e| Error::StackError(span(e.op()), e))?;
+        let delayed = graph
+            .delayed_edges()
+            .iter()
+            .map(|&(source, sink, port)| DelayedEdge {
+                span: span(origins[sink.index()]),
+                source: span(origins[source.index()]),
+                port,
+            })
+            .collect();
+        Ok((graph, delayed))
+    }
+}
+
+/// Split source into tokens with their positions, skipping `//` comments.
+/// Commas are separators as whitespace is. Brackets and braces are separators too, but they are
+/// tokens themselves.
+fn tokenize(s: &str) -> Vec<(Span, &str)> {
+    let mut tokens = Vec::new();
+    for (i, line) in s.split_terminator('\n').enumerate() {
+        let code = line.split("//").next().unwrap_or("");
+        // (column, byte offset) of the current token start
+        let mut start = None;
+        let end = std::iter::once((code.len(), ' '));
+        for (column, (offset, c)) in code.char_indices().chain(end).enumerate() {
+            let is_bracket = matches!(c, '[' | ']' | '{' | '}');
+            let is_separator = c.is_whitespace() || c == ',' || is_bracket;
+            match start {
+                None if !is_separator => start = Some((column, offset)),
+                Some((start_column, start_offset)) if is_separator => {
+                    let span = Span {
+                        line: i + 1,
+                        column: start_column + 1,
+                        len: column - start_column,
+                    };
+                    tokens.push((span, &code[start_offset..offset]));
+                    start = None;
+                }
+                _ => {}
+            }
+            if is_bracket {
+                let span = Span {
+                    line: i + 1,
+                    column: column + 1,
+                    len: 1,
+                };
+                tokens.push((span, &code[offset..offset + 1]));
+            }
+        }
+    }
+    tokens
+}
+
+struct Parser {
+    registry: Rc<Registry>,
+    channels: u8,
+    sample_rate: u32,
+    seeds: Seeds,
+    /// User-defined words and their bodies. Bodies consist of built-in tokens only, words used
+    /// in them are expanded at definition time, so redefinition doesn't affect earlier words.
+    words: HashMap<String, Vec<String>>,
+    /// Names bound with `=name` so far. They take precedence over built-in tokens.
+    bindings: HashSet<String>,
+    /// Canonical paths of files which are being parsed, to detect include cycles.
+    including: Vec<PathBuf>,
+    /// Span of the token being parsed.
+    span: Span,
+    /// Quote being read or waiting for a combinator.
+    quote: Option<Quoted>,
+    /// Index of `Begin` op of each open list, to check list length at `End`.
+    lists: Vec<usize>,
+    /// Index of the last number literal op and its value, for `repeat` count.
+    literal: Option<(usize, Sample)>,
+    /// Index of the op taken back while parsing the token, which is `repeat` count, so that
+    /// spans are updated from it.
+    taken: Option<usize>,
+}
+
+/// Tokens of the quote being read.
+struct Quoted {
+    tokens: Vec<String>,
+    /// Nesting depth of braces, 0 when the quote is read and waits for a combinator.
+    depth: usize,
+    /// Span of the opening brace.
+    span: Span,
+}
+
+/// Quote taken by a combinator. Its tokens are parsed anew for each run, so each run creates
+/// new modules.
+struct Quote {
+    registry: Rc<Registry>,
+    channels: u8,
+    sample_rate: u32,
+    /// Names bound before the quote.
+    bindings: HashSet<String>,
+    tokens: Vec<String>,
+    /// Random modules of each run take seeds from their own stream derived from this seed.
+    seed: u64,
+    /// Whether the run number, counted from 1, is pushed before each run.
+    numbered: bool,
+}
+
+impl Quote {
+    /// Parse ops of the `run`, counted from 0.
+    fn parse(&self, run: usize) -> Result<Vec<stack::Op>, ParseError> {
+        let seeds = Seeds::run(self.seed, run);
+        let mut parser = Parser::new(
+            self.registry.clone(),
+            self.channels,
+            self.sample_rate,
+            seeds,
+        );
+        parser.bindings = self.bindings.clone();
+        let mut ops = Vec::new();
+        if self.numbered {
+            parser.parse_builtin(&(run + 1).to_string(), &mut ops)?;
+        }
+        for token in &self.tokens {
+            parser.parse_builtin(token, &mut ops)?;
+        }
+        // Braces are balanced, but a nested quote might miss its combinator.
+        match parser.quote {
+            Some(_) => Err(ParseError::MissingCombinator),
+            None => Ok(ops),
+        }
+    }
+}
+
+impl Parser {
+    fn new(registry: Rc<Registry>, channels: u8, sample_rate: u32, seeds: Seeds) -> Self {
+        Parser {
+            registry,
+            channels,
+            sample_rate,
+            seeds,
+            words: HashMap::new(),
+            bindings: HashSet::new(),
+            including: Vec::new(),
+            span: Span::default(),
+            quote: None,
+            lists: Vec::new(),
+            literal: None,
+            taken: None,
+        }
+    }
+
+    /// Parse source `s` of the file at `path` into `program`.
+    /// `span`, if given, replaces spans of tokens for ops.
+    fn parse(
+        &mut self,
+        s: &str,
+        path: Option<&Path>,
+        span: Option<Span>,
+        program: &mut Program,
+    ) -> Result<(), Error> {
+        let mut tokens = tokenize(s).into_iter();
+        // Quotes don't span files.
+        let outer = self.quote.take();
+        while let Some((token_span, token)) = tokens.next() {
+            let span = span.unwrap_or(token_span);
+            self.span = token_span;
+            if token == ":" {
+                self.define(token_span, &mut tokens)?;
+            } else if let Some(include) = token.strip_prefix("include:") {
+                self.include(include, path, span, program)
+                    .map_err(|e| Error::ParseError(token_span, e))?;
+            } else {
+                self.parse_token(token, &mut program.ops)
+                    .map_err(|e| Error::ParseError(token_span, e))?;
+            }
+            if let Some(i) = self.taken.take() {
+                program.spans.truncate(i);
+            }
+            program.spans.resize(program.ops.len(), span);
+        }
+        self.end_quote(outer)
+    }
+
+    /// Parse file `include` relative to the directory of the file at `from` into `program`.
+    fn include(
+        &mut self,
+        include: &str,
+        from: Option<&Path>,
+        span: Span,
+        program: &mut Program,
+    ) -> Result<(), ParseError> {
+        let path = match from.and_then(Path::parent) {
+            Some(dir) => dir.join(include),
+            None => PathBuf::from(include),
+        };
+        let failed =
+            |e: std::io::Error| ParseError::IncludeFailed(include.to_string(), e.to_string());
+        let path = path.canonicalize().map_err(failed)?;
+        if self.including.contains(&path) {
+            return Err(ParseError::IncludeCycle(include.to_string()));
+        }
+        let source = std::fs::read_to_string(&path).map_err(failed)?;
+        if !program.includes.contains(&path) {
+            program.includes.push(path.clone());
+        }
+        self.including.push(path.clone());
+        let result = self.parse(&source, Some(&path), Some(span), program);
+        self.including.pop();
+        result.map_err(|error| ParseError::Included {
+            path,
+            source,
+            error: Box::new(error),
+        })
+    }
+
+    /// Parse word definition following `:` at `span` up to `;`.
+    fn define<'a>(
+        &mut self,
+        span: Span,
+        tokens: &mut impl Iterator<Item = (Span, &'a str)>,
+    ) -> Result<(), Error> {
+        let (name_span, name) = match tokens.next() {
+            Some((_, ";")) | None => {
+                let e = ParseError::NotEnoughParameters(":".to_string());
+                return Err(Error::ParseError(span, e));
+            }
+            Some(token) => token,
+        };
+        let mut declared = None;
+        // Body tokens with spans of tokens they come from, for error reporting.
+        let mut body = Vec::new();
+        loop {
+            match tokens.next() {
+                Some((_, ";")) => break,
+                Some((_, ":")) | None => {
+                    let e = ParseError::UnterminatedDefinition(name.to_string());
+                    return Err(Error::ParseError(name_span, e));
+                }
+                Some((span, "(")) if declared.is_none() && body.is_empty() => {
+                    declared = Some(
+                        parse_stack_effect(name, tokens).map_err(|e| Error::ParseError(span, e))?,
+                    );
+                }
+                Some((span, token)) => match self.words.get(token) {
+                    Some(word) => body.extend(word.iter().map(|token| (span, token.clone()))),
+                    None => body.push((span, token.to_string())),
+                },
+            }
+        }
+
+        // Parse body to validate it, but restore seeds, bindings, lists, quote and literal state to
+        // not affect tokens outside the word.
+        let seeds = self.seeds.clone();
+        let bindings = self.bindings.clone();
+        let lists = std::mem::take(&mut self.lists);
+        let (quote, literal, taken) = (self.quote.take(), self.literal.take(), self.taken.take());
+        let mut ops = Vec::new();
+        let result = body.iter().try_for_each(|(span, token)| {
+            self.span = *span;
+            self.parse_builtin(token, &mut ops)
+                .map_err(|e| Error::ParseError(*span, e))
+        });
+        let result = result.and(self.end_quote(quote));
+        self.seeds = seeds;
+        self.bindings = bindings;
+        self.lists = lists;
+        self.literal = literal;
+        self.taken = taken;
+        result?;
+        if let Some(declared) = declared {
+            let actual = stack::stack_effect(&ops);
+            if actual != declared {
+                let e = ParseError::WrongStackEffect {
+                    word: name.to_string(),
+                    declared,
+                    actual,
+                };
+                return Err(Error::ParseError(name_span, e));
+            }
+        }
+
+        let body = body.into_iter().map(|(_, token)| token).collect();
+        self.words.insert(name.to_string(), body);
+        Ok(())
+    }
+
+    /// Parse user-defined word or built-in token.
+    fn parse_token(&mut self, token: &str, ops: &mut Vec<stack::Op>) -> Result<(), ParseError> {
+        match self.words.get(token) {
+            Some(body) => {
+                for token in body.clone() {
+                    self.parse_builtin(&token, ops)?;
+                }
+                Ok(())
+            }
+            None => self.parse_builtin(token, ops),
+        }
+    }
+
+    /// Restore quote state to `outer`, fail if the current quote is unfinished.
+    fn end_quote(&mut self, outer: Option<Quoted>) -> Result<(), Error> {
+        match std::mem::replace(&mut self.quote, outer) {
+            Some(quote) if quote.depth > 0 => {
+                Err(Error::ParseError(quote.span, ParseError::UnbalancedQuote))
+            }
+            Some(quote) => Err(Error::ParseError(quote.span, ParseError::MissingCombinator)),
+            None => Ok(()),
+        }
+    }
+
+    fn parse_builtin(&mut self, token: &str, ops: &mut Vec<stack::Op>) -> Result<(), ParseError> {
+        if let Some(quote) = &mut self.quote {
+            if quote.depth == 0 {
+                let tokens = std::mem::take(&mut quote.tokens);
+                self.quote = None;
+                return self.combinator(token, tokens, ops);
+            }
+            match token {
+                "{" => quote.depth += 1,
+                "}" => quote.depth -= 1,
+                _ => {}
+            }
+            if quote.depth > 0 {
+                quote.tokens.push(token.to_string());
+            }
+            return Ok(());
+        }
         match token {
-            "*" => connect!(ops, Fn2, channels, pure::mul),
-            "+" => connect!(ops, Fn2, channels, pure::add),
-            "-" => connect!(ops, Fn2, channels, pure::sub),
-            "/" => connect!(ops, Fn2, channels, pure::div),
-            "\\" => connect!(ops, Fn1, channels, pure::recip),
-            "^" | "pow" => connect!(ops, Fn2, channels, pure::pow),
-            "cheb2" => connect!(ops, Fn1, channels, pure::cheb2),
-            "cheb3" => connect!(ops, Fn1, channels, pure::cheb3),
-            "cheb4" => connect!(ops, Fn1, channels, pure::cheb4),
-            "cheb5" => connect!(ops, Fn1, channels, pure::cheb5),
-            "cheb6" => connect!(ops, Fn1, channels, pure::cheb6),
-            "cos" => connect!(ops, Fn1, channels, pure::cos),
-            "dm" | "dmetro" => connect!(ops, DMetro, channels, sample_rate),
-            "dmh" | "dmetro_hold" => connect!(ops, DMetroHold, channels, sample_rate),
-            "h" | "bqhpf" => connect!(ops, BiQuad, channels, sample_rate, make_hpf_coefficients),
-            "hpf" => connect!(ops, HPF, channels, sample_rate),
-            "impulse" => {
-                use envelopes::Impulse;
-                connect!(ops, Impulse, channels, sample_rate)
-            }
-            "in" | "input" => connect!(ops, Input, channels),
-            "l" | "bqlpf" => connect!(ops, BiQuad, channels, sample_rate, make_lpf_coefficients),
-            "lpf" => connect!(ops, LPF, channels, sample_rate),
-            "m" | "metro" => connect!(ops, Metro, channels, sample_rate),
-            "m2f" | "midi2freq" => connect!(ops, Fn1, channels, pure::midi2freq),
-            "mh" | "metro_hold" => connect!(ops, MetroHold, channels, sample_rate),
-            "n" | "noise" => connect!(ops, WhiteNoise, channels),
-            "p" | "pulse" => connect!(ops, Pulse, channels, sample_rate),
-            "pan1" => connect!(ops, Pan1, channels),
-            "pan2" => connect!(ops, Pan2, channels),
-            "q" | "quantize" => connect!(ops, Fn2, channels, pure::quantize),
-            "r" | "range" => connect!(ops, Fn3, channels, pure::range),
-            "round" => connect!(ops, Fn1, channels, pure::round),
-            "s" => connect!(ops, Osc, channels, sample_rate, pure::sine),
-            "saw" => connect!(ops, Phasor0, channels, sample_rate),
-            "sh" | "sample&hold" => connect!(ops, SampleAndHold, channels),
-            "sin" => connect!(ops, Fn1, channels, pure::sin),
-            "sine" => connect!(ops, OscPhase, channels, sample_rate, pure::sine),
-            "spectral_shuffle" => {
-                let mut rng = Box::new(SmallRng::from_entropy());
-                connect!(
-                    ops,
-                    SpectralTransform,
-                    channels,
-                    2048,
-                    64,
-                    Box::new(move |freqs| freqs.shuffle(&mut rng)),
-                )
-            }
-            "t" => connect!(ops, Osc, channels, sample_rate, pure::triangle),
-            "tri" => connect!(ops, OscPhase, channels, sample_rate, pure::triangle),
-            "unit" => connect!(ops, Fn1, channels, pure::unit),
-            "w" => connect!(ops, Phasor, channels, sample_rate),
-            // TODO parametrize
-            "yin" => connect!(ops, Yin, channels, sample_rate, 1024, 64, 0.2),
-            "zip" => connect!(ops, Zip, channels),
-            "dup" => ops.push(stack::Op::Dup),
-            "swap" => ops.push(stack::Op::Swap),
-            "rot" => ops.push(stack::Op::Rot),
-            "pop" => ops.push(stack::Op::Pop),
-            _ => match token.parse::<Sample>() {
-                Ok(x) => connect!(ops, Constant, channels, x),
-                Err(_) => {
-                    let subcmd = token.split(':').collect::<Vec<_>>();
-                    match subcmd[0] {
-                        "delay" => match subcmd.get(1) {
-                            Some(x) => match x.parse::<f64>() {
-                                Ok(max_delay) => {
-                                    connect!(ops, Delay, channels, sample_rate, max_delay)
-                                }
-                                Err(_) => {
-                                    return Err(ParseError::WrongParameterType(token.to_string()));
-                                }
-                            },
-                            None => connect!(ops, Delay, channels, sample_rate, 60.0),
-                        },
-                        "fb" | "feedback" => match subcmd.get(1) {
-                            Some(x) => match x.parse::<f64>() {
-                                Ok(max_delay) => {
-                                    connect!(ops, Feedback, channels, sample_rate, max_delay)
-                                }
-                                Err(_) => {
-                                    return Err(ParseError::WrongParameterType(token.to_string()));
-                                }
-                            },
-                            None => connect!(ops, Feedback, channels, sample_rate, 60.0),
-                        },
-                        "conv" => match subcmd.get(1) {
-                            Some(x) => match x.parse::<usize>() {
-                                Ok(window_size) => {
-                                    connect!(ops, Convolution, channels, window_size)
-                                }
-                                Err(_) => {
-                                    return Err(ParseError::WrongParameterType(token.to_string()));
-                                }
-                            },
-                            None => return Err(ParseError::NotEnoughParameters(token.to_string())),
-                        },
-                        "convm" => match subcmd.get(1) {
-                            Some(x) => match x.parse::<usize>() {
-                                Ok(window_size) => {
-                                    connect!(ops, ConvolutionM, channels, window_size)
-                                }
-                                Err(_) => {
-                                    return Err(ParseError::WrongParameterType(token.to_string()));
-                                }
-                            },
-                            None => return Err(ParseError::NotEnoughParameters(token.to_string())),
-                        },
-                        "param" => match subcmd.get(1) {
-                            Some(x) => match x.parse::<u8>() {
-                                Ok(index) => connect!(ops, Parameter, channels, index),
-                                Err(_) => {
-                                    return Err(ParseError::WrongParameterType(token.to_string()));
-                                }
-                            },
-                            None => return Err(ParseError::NotEnoughParameters(token.to_string())),
-                        },
-                        _ => return Err(ParseError::UnknownToken(token.to_string())),
+            "{" => {
+                self.quote = Some(Quoted {
+                    tokens: Vec::new(),
+                    depth: 1,
+                    span: self.span,
+                });
+                return Ok(());
+            }
+            "}" => return Err(ParseError::UnbalancedQuote),
+            _ => {}
+        }
+        if let Some(name) = token.strip_prefix('=').filter(|name| !name.is_empty()) {
+            self.bindings.insert(name.to_string());
+            ops.push(stack::Op::Set(name.to_string()));
+            return Ok(());
+        }
+        if self.bindings.contains(token) {
+            ops.push(stack::Op::Get(token.to_string()));
+            return Ok(());
+        }
+        let op = match token {
+            "dup" => stack::Op::Dup,
+            "swap" => stack::Op::Swap,
+            "rot" => stack::Op::Rot,
+            "pop" | "drop" => stack::Op::Pop,
+            "over" => stack::Op::Over,
+            "nip" => stack::Op::Nip,
+            "tuck" => stack::Op::Tuck,
+            "-rot" => stack::Op::RotBack,
+            "2dup" => stack::Op::Dup2,
+            "2swap" => stack::Op::Swap2,
+            "[" => {
+                self.lists.push(ops.len());
+                stack::Op::Begin
+            }
+            "]" => {
+                // Unbalanced brackets are reported when ops are checked or built.
+                if let Some(begin) = self.lists.pop() {
+                    let (_, len) = stack::stack_effect(&ops[begin + 1..]);
+                    if len > stack::MAX_LIST_LEN {
+                        return Err(ParseError::ListTooLong(len));
+                    }
+                }
+                stack::Op::End
+            }
+            _ => {
+                let op = self.registry.construct(
+                    token,
+                    self.channels,
+                    self.sample_rate,
+                    &mut self.seeds,
+                );
+                match op {
+                    Some(op) => op?,
+                    None => return self.parse_other(token, ops),
+                }
+            }
+        };
+        ops.push(op);
+        Ok(())
+    }
+
+    /// Create op running the quote of `tokens` for combinator `token`, ref `parse_ops`.
+    fn combinator(
+        &mut self,
+        token: &str,
+        tokens: Vec<String>,
+        ops: &mut Vec<stack::Op>,
+    ) -> Result<(), ParseError> {
+        let (numbered, mix) = match token {
+            "map" => (false, false),
+            "map-mix" => (false, true),
+            "repeat" => (true, false),
+            "repeat-mix" => (true, true),
+            _ => return Err(ParseError::MissingCombinator),
+        };
+        let count = if numbered {
+            match self.literal {
+                Some((i, x)) if i + 1 == ops.len() && x >= 0.0 && x.fract() == 0.0 => {
+                    if x > stack::MAX_LIST_LEN as Sample {
+                        return Err(ParseError::ListTooLong(x as usize));
                     }
+                    ops.pop();
+                    self.taken = Some(i);
+                    Some(x as usize)
                 }
+                _ => return Err(ParseError::MissingRepeatCount(token.to_string())),
+            }
+        } else {
+            None
+        };
+        let quote = Quote {
+            registry: self.registry.clone(),
+            channels: self.channels,
+            sample_rate: self.sample_rate,
+            bindings: self.bindings.clone(),
+            tokens,
+            seed: self.seeds.next(),
+            numbered,
+        };
+        // Runs differ only in seeds and run numbers, so parsing one is enough to find errors.
+        quote.parse(0)?;
+        let quote: stack::Quote =
+            Box::new(move |run| quote.parse(run).expect("Quote is checked when it's taken"));
+        ops.push(match count {
+            Some(n) => stack::Op::Repeat(n, quote),
+            None => stack::Op::Map(quote),
+        });
+        if mix {
+            let channels = self.channels;
+            ops.push(registry::list_node(0, move |size| {
+                Mix::new(channels, size)
+            })?);
+        }
+        Ok(())
+    }
+
+    /// Parse numbers, stack words with parameters and directives.
+    fn parse_other(&mut self, token: &str, ops: &mut Vec<stack::Op>) -> Result<(), ParseError> {
+        if let Some(x) = literal::parse(token) {
+            self.literal = Some((ops.len(), x));
+            // Constants have no sources, so they are never expanded.
+            ops.push(stack::Op::Connect(
+                Box::new(Constant::new(self.channels, x)),
+                None,
+            ));
+            return Ok(());
+        }
+        let mut subcmd = token.split(':');
+        match (subcmd.next(), subcmd.next()) {
+            (Some(name @ "pick"), Some(x))
+            | (Some(name @ "roll"), Some(x))
+            | (Some(name @ "drop"), Some(x)) => {
+                let n = x
+                    .parse::<u8>()
+                    .map_err(|_| ParseError::WrongParameterType(token.to_string()))?
+                    as usize;
+                ops.push(match name {
+                    "pick" => stack::Op::Pick(n),
+                    "roll" => stack::Op::Roll(n),
+                    _ => stack::Op::Drop(n),
+                });
+            }
+            (Some("pick"), None) | (Some("roll"), None) => {
+                return Err(ParseError::NotEnoughParameters(token.to_string()))
+            }
+            (Some("seed"), Some(x)) => match x.parse::<u64>() {
+                Ok(seed) => self.seeds = Seeds::new(seed),
+                Err(_) => return Err(ParseError::WrongParameterType(token.to_string())),
             },
+            (Some("seed"), None) => return Err(ParseError::NotEnoughParameters(token.to_string())),
+            _ => return Err(ParseError::UnknownToken(token.to_string())),
         }
+        Ok(())
+    }
+}
+
+/// Parse stack effect comment `( in1 in2 -- out1 )` following `(` into (inputs, outputs) counts.
+fn parse_stack_effect<'a>(
+    name: &str,
+    tokens: &mut impl Iterator<Item = (Span, &'a str)>,
+) -> Result<(usize, usize), ParseError> {
+    let (mut inputs, mut outputs) = (0, None);
+    loop {
+        match tokens.next().map(|(_, token)| token) {
+            Some(")") => break,
+            Some("--") if outputs.is_none() => outputs = Some(0),
+            Some("--") | Some(";") | None => {
+                return Err(ParseError::MalformedStackEffect(name.to_string()));
+            }
+            Some(_) => match &mut outputs {
+                Some(outputs) => *outputs += 1,
+                None => inputs += 1,
+            },
+        }
+    }
+    match outputs {
+        Some(outputs) => Ok((inputs, outputs)),
+        None => Err(ParseError::MalformedStackEffect(name.to_string())),
     }
-    Ok(ops)
 }
 
 pub fn parse_graph(
@@ -159,9 +957,391 @@ pub fn parse_graph(
     channels: u8,
     sample_rate: u32,
     inputs: u8,
+    seed: u64,
+    path: Option<&Path>,
 ) -> Result<AudioGraph, Error> {
-    match parse_ops(s, channels, sample_rate) {
-        Ok(ops) => stack::build_graph(ops, channels, inputs).or_else(|e| Err(Error::StackError(e))),
-        Err(e) => Err(Error::ParseError(e)),
+    parse_ops(s, channels, sample_rate, seed, path)?.build(channels, inputs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use audio_graph::Module;
+
+    fn parse(s: &str) -> Result<AudioGraph, Error> {
+        parse_graph(s, 1, 48_000, 0, 0, None)
+    }
+
+    fn span(column: usize, len: usize) -> Span {
+        Span {
+            line: 1,
+            column,
+            len,
+        }
+    }
+
+    /// Source of a list of `n` numbers.
+    fn list(n: usize) -> String {
+        let items = (1..=n).map(|i| i.to_string()).collect::<Vec<_>>();
+        format!("[{}]", items.join(" "))
+    }
+
+    #[test]
+    fn lists() {
+        assert!(parse("[c4 eb4 g4] 4 m seq s").is_ok());
+        assert!(parse("[] pop 1").is_ok());
+        assert!(parse("440 =f [f f 3/2 *] 4 m seq s").is_ok());
+    }
+
+    #[test]
+    fn nested_lists() {
+        match parse("[1 [2 3]] 4 m seq") {
+            Err(Error::StackError(span_, stack::Error::UnexpectedList(_))) => {
+                assert_eq!(span_, span(9, 1))
+            }
+            result => panic!("unexpected result: {:?}", result.err()),
+        }
+    }
+
+    #[test]
+    fn unbalanced_lists() {
+        match parse("1 [2 3") {
+            Err(Error::StackError(span_, stack::Error::UnbalancedList(_))) => {
+                assert_eq!(span_, span(3, 1))
+            }
+            result => panic!("unexpected result: {:?}", result.err()),
+        }
+        match parse("1 2]") {
+            Err(Error::StackError(span_, stack::Error::UnbalancedList(_))) => {
+                assert_eq!(span_, span(4, 1))
+            }
+            result => panic!("unexpected result: {:?}", result.err()),
+        }
+    }
+
+    #[test]
+    fn long_lists() {
+        let max = stack::MAX_LIST_LEN;
+        let source = format!("{} 4 m seq s", list(max));
+        assert!(parse(&source).is_ok());
+        let source = format!("1 {} 4 m seq s", list(max + 1));
+        let end = source.find(']').unwrap() + 1;
+        match parse(&source) {
+            Err(Error::ParseError(span_, ParseError::ListTooLong(len))) => {
+                assert_eq!(span_, span(end, 1));
+                assert_eq!(len, max + 1);
+            }
+            result => panic!("unexpected result: {:?}", result.err()),
+        }
+    }
+
+    #[test]
+    fn mix_long_lists() {
+        for mix in &["mix", "splay"] {
+            let source = format!("{} s {}", list(20), mix);
+            assert!(parse(&source).is_ok(), "{}", source);
+            let source = format!("{} s {}", list(stack::MAX_LIST_LEN + 1), mix);
+            let end = source.find(']').unwrap() + 1;
+            match parse(&source) {
+                Err(Error::ParseError(span_, ParseError::ListTooLong(_))) => {
+                    assert_eq!(span_, span(end, 1))
+                }
+                result => panic!("unexpected result: {:?}", result.err()),
+            }
+        }
+    }
+
+    #[test]
+    fn channels() {
+        let source = "440 s 0.5 pan1 chan:1";
+        assert!(parse_graph(source, 2, 48_000, 0, 0, None).is_ok());
+        match parse(source) {
+            Err(Error::ParseError(span_, ParseError::ChannelOutOfRange { channels: 1, .. })) => {
+                assert_eq!(span_, span(16, 6))
+            }
+            result => panic!("unexpected result: {:?}", result.err()),
+        }
+    }
+
+    /// Render 100 frames of the source.
+    fn render(s: &str) -> Vec<Sample> {
+        let mut graph = parse(s).unwrap();
+        let mut output = vec![0.0; 100];
+        graph.process(&[], &mut output, 100);
+        output
+    }
+
+    /// Span of the parse error of the source.
+    fn parse_error(s: &str) -> (Span, ParseError) {
+        match parse(s) {
+            Err(Error::ParseError(span, e)) => (span, e),
+            result => panic!("unexpected result of `{}`: {:?}", s, result.err()),
+        }
+    }
+
+    #[test]
+    fn quotes() {
+        assert_eq!(
+            render("20 { 100 * s } repeat-mix"),
+            render(&(1..=20).fold("0".to_string(), |s, i| format!("{} {}00 s +", s, i))),
+        );
+        assert_eq!(
+            render("[1 2] { 3 { 100 * s } repeat-mix * } map-mix"),
+            render("100 s 200 s + 300 s + 2 * 100 s 200 s + 300 s + +"),
+        );
+        let max = stack::MAX_LIST_LEN;
+        assert!(parse(&format!("{} {{ s }} repeat-mix", max)).is_ok());
+        match parse_error(&format!("{} {{ s }} repeat-mix", max + 1)) {
+            (span_, ParseError::ListTooLong(len)) => {
+                assert_eq!(span_, span(11, 10));
+                assert_eq!(len, max + 1);
+            }
+            (_, e) => panic!("unexpected error: {}", e),
+        }
+    }
+
+    #[test]
+    fn unbalanced_quotes() {
+        for &(source, column) in &[("1 { 2", 3), ("1 }", 3), ("{ { 1 } repeat", 1)] {
+            match parse_error(source) {
+                (span_, ParseError::UnbalancedQuote) => assert_eq!(span_, span(column, 1)),
+                (_, e) => panic!("unexpected error of `{}`: {}", source, e),
+            }
+        }
+        for &(source, column, len) in &[("{ 1 }", 1, 1), ("3 { { 1 } } repeat", 13, 6)] {
+            match parse_error(source) {
+                (span_, ParseError::MissingCombinator) => assert_eq!(span_, span(column, len)),
+                (_, e) => panic!("unexpected error of `{}`: {}", source, e),
+            }
+        }
+    }
+
+    #[test]
+    fn repeat_count() {
+        for source in &[
+            "3 =n n { s } repeat",
+            "1.5 { s } repeat",
+            "1 2 + { s } repeat",
+        ] {
+            match parse_error(source) {
+                (_, ParseError::MissingRepeatCount(token)) => assert_eq!(token, "repeat"),
+                (_, e) => panic!("unexpected error of `{}`: {}", source, e),
+            }
+        }
+    }
+
+    #[test]
+    fn words_and_bindings_in_quotes() {
+        assert_eq!(
+            render(": voice ( f -- sig ) s 0.3 * ; 2 { 100 * voice } repeat-mix"),
+            render("0 100 s 0.3 * + 200 s 0.3 * +"),
+        );
+        assert_eq!(
+            render("3 =x [1 2] { x * s } map-mix"),
+            render("0 3 s + 6 s +"),
+        );
+        // Names bound in a quote are not bound outside of it.
+        match parse_error("2 { =y y s } repeat pop y") {
+            (span_, ParseError::UnknownToken(token)) => {
+                assert_eq!(span_, span(25, 1));
+                assert_eq!(token, "y");
+            }
+            (_, e) => panic!("unexpected error: {}", e),
+        }
+    }
+
+    #[test]
+    fn delayed_edges() {
+        let program = parse_ops("440 s 0.5 *", 1, 48_000, 0, None).unwrap();
+        let (_, delayed) = program.build_with_delayed_edges(1, 0).unwrap();
+        // Sources are always created before their sinks, so stack source has no cycles.
+        assert!(delayed.is_empty());
+        let note = DelayedEdge {
+            span: span(5, 1),
+            source: span(11, 1),
+            port: Port(0),
+        };
+        assert_eq!(
+            note.render("440 s 0.5 *"),
+            "note: input 0 from 1:11 is delayed by one frame to break a cycle\n --> 1:5\n  |\n\
+             1 | 440 s 0.5 *\n  |     ^\n"
+        );
+    }
+
+    /// Create files `(path, source)` in a new temporary directory unique for the test `name` and
+    /// return the directory.
+    fn temp_files(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("audio_stack-{}-{}", std::process::id(), name));
+        for (path, source) in files {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, source).unwrap();
+        }
+        dir
+    }
+
+    /// Parse the file at `path` as the source of the patch.
+    fn parse_file(path: &Path) -> Result<Program, Error> {
+        let source = std::fs::read_to_string(path).unwrap();
+        parse_ops(&source, 1, 48_000, 0, Some(path))
+    }
+
+    #[test]
+    fn includes_are_relative_to_the_file() {
+        let dir = temp_files(
+            "relative",
+            &[
+                ("main.sg", "include:lib/osc.sg 0.5 *"),
+                ("lib/osc.sg", "include:freq.sg s"),
+                ("lib/freq.sg", ": freq 440 ; freq"),
+            ],
+        );
+        let program = parse_file(&dir.join("main.sg")).unwrap();
+        let includes = vec![
+            dir.join("lib/osc.sg").canonicalize().unwrap(),
+            dir.join("lib/freq.sg").canonicalize().unwrap(),
+        ];
+        assert_eq!(program.includes, includes);
+        // Ops of included files point to the `include:` token of the main file.
+        assert_eq!(program.spans[0], span(1, 18));
+        assert!(program.build(1, 0).is_ok());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn include_cycles() {
+        let dir = temp_files(
+            "cycles",
+            &[
+                ("self.sg", "1 include:self.sg"),
+                ("a.sg", "include:b.sg"),
+                ("b.sg", "include:a.sg"),
+            ],
+        );
+        match parse_file(&dir.join("self.sg")) {
+            Err(Error::ParseError(span_, ParseError::IncludeCycle(path))) => {
+                assert_eq!(span_, span(3, 15));
+                assert_eq!(path, "self.sg");
+            }
+            result => panic!("unexpected result: {:?}", result.err()),
+        }
+        let error = parse_file(&dir.join("a.sg")).err().unwrap();
+        assert_eq!(
+            error.included(),
+            vec![dir.join("b.sg").canonicalize().unwrap()]
+        );
+        match error {
+            Error::ParseError(_, ParseError::Included { error, .. }) => match *error {
+                Error::ParseError(_, ParseError::IncludeCycle(path)) => assert_eq!(path, "a.sg"),
+                e => panic!("unexpected error: {}", e),
+            },
+            e => panic!("unexpected error: {}", e),
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn errors_in_included_files() {
+        let dir = temp_files(
+            "errors",
+            &[
+                ("main.sg", "440 s\ninclude:lib.sg +"),
+                ("lib.sg", "include:osc.sg"),
+                ("osc.sg", "220 sinn"),
+            ],
+        );
+        let main = dir.join("main.sg");
+        let error = parse_file(&main).err().unwrap();
+        let canonical = |path: &str| dir.join(path).canonicalize().unwrap();
+        assert_eq!(
+            error.included(),
+            vec![canonical("lib.sg"), canonical("osc.sg")]
+        );
+        let rendered = error.render(&std::fs::read_to_string(&main).unwrap());
+        let expected = format!(
+            "error: in included file `{}`\n --> 2:1\n  |\n\
+             2 | include:lib.sg +\n  | ^^^^^^^^^^^^^^\n\
+             error: in included file `{}`\n --> 1:1\n  |\n\
+             1 | include:osc.sg\n  | ^^^^^^^^^^^^^^\n\
+             error: unknown token `sinn`\n --> 1:5\n  |\n1 | 220 sinn\n  |     ^^^^\n",
+            canonical("lib.sg").display(),
+            canonical("osc.sg").display(),
+        );
+        assert_eq!(rendered, expected);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn oscillators_are_not_merged() {
+        // Constants are merged, oscillators are not.
+        let graph = parse("440 s 440 s +").unwrap();
+        assert_eq!(graph.layout().len(), 4);
+    }
+
+    /// Check the source and return warnings as (span, warning) and how many items are left.
+    fn check(s: &str) -> (Vec<(Span, stack::Warning)>, usize) {
+        let program = parse_ops(s, 1, 48_000, 0, None).unwrap();
+        let (warnings, outputs) = program.check().unwrap();
+        let warnings = warnings.into_iter().map(|w| (w.span, w.warning));
+        (warnings.collect(), outputs)
+    }
+
+    #[test]
+    fn check_without_warnings() {
+        assert_eq!(check("440 s 2 *"), (vec![], 1));
+        assert_eq!(check("440 =f f s f 2 * s +"), (vec![], 1));
+        assert_eq!(check("[1 2] { s } map-mix"), (vec![], 1));
+        assert_eq!(check(""), (vec![], 0));
+    }
+
+    #[test]
+    fn check_discarded() {
+        let (warnings, outputs) = check("440 s 220 s");
+        assert_eq!(outputs, 2);
+        assert_eq!(warnings.len(), 1);
+        assert!(matches!(warnings[0], (s, stack::Warning::Discarded(1)) if s == span(5, 1)));
+        let (warnings, outputs) = check("1 2 3");
+        assert_eq!(outputs, 3);
+        let spans = warnings.iter().map(|(s, _)| *s).collect::<Vec<_>>();
+        assert_eq!(spans, vec![span(1, 1), span(3, 1)]);
+    }
+
+    #[test]
+    fn check_unused() {
+        let (warnings, outputs) = check("440 s pop 1");
+        assert_eq!(outputs, 1);
+        assert_eq!(warnings.len(), 1);
+        assert!(matches!(warnings[0], (s, stack::Warning::Unused(1)) if s == span(5, 1)));
+        // Nodes of the discarded list are reported once.
+        let (warnings, outputs) = check("[1 2] s 3");
+        assert_eq!(outputs, 2);
+        assert_eq!(warnings.len(), 1);
+        assert!(matches!(warnings[0], (_, stack::Warning::Discarded(_))));
+    }
+
+    #[test]
+    fn check_empty_quote() {
+        assert_eq!(check("[1 2] { } map-mix"), (vec![], 1));
+        assert_eq!(check("[1 2] { } map mix"), (vec![], 1));
+    }
+
+    #[test]
+    fn check_errors() {
+        let program = parse_ops("1 +", 1, 48_000, 0, None).unwrap();
+        match program.check() {
+            Err(Error::StackError(span_, stack::Error::StackExhausted { .. })) => {
+                assert_eq!(span_, span(3, 1))
+            }
+            result => panic!("unexpected result: {:?}", result.err()),
+        }
+    }
+
+    #[test]
+    fn spans_after_definition_with_repeat() {
+        let source = "440 s\n: foo 3 { s } repeat-mix ;\n1 2 3 4 +";
+        let program = parse_ops(source, 1, 48_000, 0, None).unwrap();
+        assert_eq!(program.spans[1], span(5, 1));
+        let (warnings, _) = program.check().unwrap();
+        assert_eq!(warnings[0].span, span(5, 1));
     }
 }
@@ -0,0 +1,121 @@
+//! # Literals
+//!
+//! Besides plain numbers source might contain numbers with units, which are converted to the
+//! units modules expect:
+//! - time: `250ms`, `2s` (to seconds);
+//! - frequency: `440hz`, `1.5khz` (to Hz);
+//! - gain: `-6db` (to linear gain);
+//! - notes: `c4`, `f#3`, `bb2` (to Hz, `a4` is 440 Hz);
+//! - ratios: `3/2`.
+//!
+//! Units are case-insensitive, e.g. `1.5kHz` and `-6dB` are fine too.
+use audio_graph::Sample;
+
+/// Parse number or number with unit, ref module docs.
+pub(crate) fn parse(token: &str) -> Option<Sample> {
+    if let Ok(x) = token.parse::<Sample>() {
+        return Some(x);
+    }
+    let lower = token.to_lowercase();
+    // Longer units go first to not mistake `ms` for `s`.
+    for unit in &["khz", "hz", "ms", "s", "db"] {
+        if let Some(x) = lower.strip_suffix(unit) {
+            let x = x.parse::<Sample>().ok()?;
+            return Some(match *unit {
+                "khz" => x * 1000.0,
+                "ms" => x / 1000.0,
+                "db" => (10.0 as Sample).powf(x / 20.0),
+                _ => x,
+            });
+        }
+    }
+    if let Some(pos) = token.find('/') {
+        let numerator = token[..pos].parse::<Sample>().ok()?;
+        let denominator = token[pos + 1..].parse::<Sample>().ok()?;
+        return if denominator == 0.0 {
+            None
+        } else {
+            Some(numerator / denominator)
+        };
+    }
+    note(&lower)
+}
+
+/// Frequency of note like `c4` or `f#3`.
+fn note(token: &str) -> Option<Sample> {
+    let mut chars = token.chars();
+    let pitch_class = match chars.next()? {
+        'c' => 0,
+        'd' => 2,
+        'e' => 4,
+        'f' => 5,
+        'g' => 7,
+        'a' => 9,
+        'b' => 11,
+        _ => return None,
+    };
+    let rest = chars.as_str();
+    let (accidental, octave) = if let Some(octave) = rest.strip_prefix('#') {
+        (1, octave)
+    } else if let Some(octave) = rest.strip_prefix('b') {
+        (-1, octave)
+    } else {
+        (0, rest)
+    };
+    // Require digits to not take a word like `add` for a note.
+    if octave.is_empty()
+        || !octave
+            .trim_start_matches('-')
+            .chars()
+            .all(|c| c.is_ascii_digit())
+    {
+        return None;
+    }
+    let octave = octave.parse::<i32>().ok()?;
+    let midi = 12 * (octave + 1) + pitch_class + accidental;
+    Some(440.0 * (2.0 as Sample).powf((midi - 69) as Sample / 12.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn units() {
+        for &(token, expected) in &[
+            ("250ms", 0.25),
+            ("2s", 2.0),
+            ("440hz", 440.0),
+            ("1.5kHz", 1500.0),
+            ("-6db", 0.501_187),
+            ("0dB", 1.0),
+            ("3/2", 1.5),
+            ("-1/4", -0.25),
+        ] {
+            let x = parse(token).unwrap();
+            assert!((x - expected).abs() < 1e-6, "{} = {}", token, x);
+        }
+    }
+
+    #[test]
+    fn notes() {
+        for &(token, expected) in &[
+            ("a4", 440.0),
+            ("A4", 440.0),
+            ("c4", 261.625_565),
+            ("f#3", 184.997_211),
+            ("bb2", 116.540_940),
+            ("c-1", 8.175_799),
+        ] {
+            let x = parse(token).unwrap();
+            assert!((x - expected).abs() < 1e-6, "{} = {}", token, x);
+        }
+    }
+
+    #[test]
+    fn not_literals() {
+        for token in &["1/0", "add", "b", "c#", "a4x", "ms", "1/", "/2"] {
+            assert_eq!(parse(token), None, "{}", token);
+        }
+    }
+}
@@ -0,0 +1,555 @@
+//! # Module registry
+//!
+//! Registry maps source tokens to modules. Each module is registered under a name and optional
+//! aliases together with its parameters and a constructor:
+//! ```text
+//! registry.register(&["dl", "delay"], &[Param::float("max", Some(60.0))], |ctx| {
+//!     node(Delay::new(ctx.channels, ctx.sample_rate, ctx.float("max")))
+//! });
+//! ```
+//! Parameters are given in the token after the name and separated by `:`, either positionally
+//! in the order of registration or by name, e.g. `yin:2048` and `yin:threshold=0.1`. Named
+//! parameters are `key=value` rather than `key:value`, as the latter couldn't be told apart from
+//! two positional ones. Parameters without default must be given. Values of float parameters
+//! might have units, e.g. `delay:max=500ms`, as number literals in source might.
+//!
+//! `Registry::builtin()` contains all built-in modules, downstream crates could add their own
+//! and pass the registry to `parse_ops_with`.
+use crate::{ParseError, Seeds};
+use audio_graph::{stack::Op, AudioNode, Module, Sample};
+use audio_modules::*;
+use rand::{seq::SliceRandom, SeedableRng};
+use rand_pcg::Pcg64Mcg;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::rc::Rc;
+
+/// Constructors are shared with factories of ops they create, ref `Registry::construct`.
+pub type Constructor = Rc<dyn Fn(&mut Context) -> Result<Op, ParseError>>;
+
+/// Type of parameter's value.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Kind {
+    Int,
+    Float,
+    /// One of the names, its value is the index of the name.
+    Choice(&'static [&'static str]),
+}
+
+/// Parameter of module's token.
+#[derive(Clone, Copy, Debug)]
+pub struct Param {
+    pub name: &'static str,
+    pub kind: Kind,
+    pub default: Option<f64>,
+}
+
+impl Param {
+    pub fn int(name: &'static str, default: Option<i64>) -> Self {
+        Param {
+            name,
+            kind: Kind::Int,
+            default: default.map(|x| x as f64),
+        }
+    }
+
+    pub fn float(name: &'static str, default: Option<f64>) -> Self {
+        Param {
+            name,
+            kind: Kind::Float,
+            default,
+        }
+    }
+
+    pub fn choice(
+        name: &'static str,
+        choices: &'static [&'static str],
+        default: Option<usize>,
+    ) -> Self {
+        Param {
+            name,
+            kind: Kind::Choice(choices),
+            default: default.map(|x| x as f64),
+        }
+    }
+
+    fn parse(&self, token: &str, value: &str) -> Result<f64, ParseError> {
+        let wrong_type = || ParseError::WrongParameterType(token.to_string());
+        match self.kind {
+            Kind::Int => value
+                .parse::<i64>()
+                .map(|x| x as f64)
+                .map_err(|_| wrong_type()),
+            Kind::Float => crate::literal::parse(value).ok_or_else(wrong_type),
+            Kind::Choice(choices) => choices
+                .iter()
+                .position(|&choice| choice == value)
+                .map(|i| i as f64)
+                .ok_or_else(wrong_type),
+        }
+    }
+}
+
+/// What constructor knows about the module being created.
+pub struct Context<'a> {
+    pub channels: u8,
+    pub sample_rate: u32,
+    token: &'a str,
+    params: &'a [Param],
+    values: &'a [f64],
+    seeds: &'a mut Seeds,
+    /// The first seed given to the constructor.
+    first_seed: Option<u64>,
+}
+
+impl<'a> Context<'a> {
+    /// Value of the parameter `name`.
+    ///
+    /// Panics if the module has no such parameter.
+    pub fn float(&self, name: &str) -> f64 {
+        match self.params.iter().position(|param| param.name == name) {
+            Some(i) => self.values[i],
+            None => panic!("`{}` has no parameter `{}`", self.token, name),
+        }
+    }
+
+    /// Value of the integer parameter `name`, which must fit `T`.
+    ///
+    /// Panics if the module has no such parameter.
+    pub fn int<T: TryFrom<i64>>(&self, name: &str) -> Result<T, ParseError> {
+        T::try_from(self.float(name) as i64)
+            .map_err(|_| ParseError::WrongParameterType(self.token.to_string()))
+    }
+
+    /// Seed for a random module, ref `parse_ops` on how it's derived.
+    pub fn seed(&mut self) -> u64 {
+        let seed = self.seeds.next();
+        self.first_seed.get_or_insert(seed);
+        seed
+    }
+}
+
+#[derive(Clone)]
+struct Definition {
+    params: Vec<Param>,
+    constructor: Constructor,
+}
+
+#[derive(Clone, Default)]
+pub struct Registry {
+    /// Definitions are shared by the name and aliases.
+    names: HashMap<String, usize>,
+    definitions: Vec<Definition>,
+}
+
+impl Registry {
+    /// Registry without any modules.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Register module under `names`, which are the name and aliases. Names registered before
+    /// are overridden.
+    pub fn register<F>(&mut self, names: &[&str], params: &[Param], constructor: F)
+    where
+        F: Fn(&mut Context) -> Result<Op, ParseError> + 'static,
+    {
+        let index = self.definitions.len();
+        self.definitions.push(Definition {
+            params: params.to_vec(),
+            constructor: Rc::new(constructor),
+        });
+        for name in names {
+            self.names.insert(name.to_string(), index);
+        }
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.names.contains_key(name)
+    }
+
+    /// Create op for `token` of the form `name:param1:param2=value...`.
+    /// Returns `None` if nothing is registered under the name.
+    ///
+    /// `Op::Connect` gets a factory which runs the constructor again, so the node could be
+    /// expanded over lists. Random nodes made by the factory take seeds from their own stream
+    /// derived from the seed of the first node, not to shift seeds of the modules which follow.
+    pub(crate) fn construct(
+        &self,
+        token: &str,
+        channels: u8,
+        sample_rate: u32,
+        seeds: &mut Seeds,
+    ) -> Option<Result<Op, ParseError>> {
+        let mut parts = token.split(':');
+        let name = parts.next().unwrap_or(token);
+        let definition = &self.definitions[*self.names.get(name)?];
+        let params = &definition.params;
+        let mut values = params.iter().map(|param| param.default).collect::<Vec<_>>();
+        for (i, part) in parts.enumerate() {
+            let (i, value) = match part.find('=') {
+                Some(pos) => {
+                    let key = &part[..pos];
+                    match params.iter().position(|param| param.name == key) {
+                        Some(i) => (i, &part[pos + 1..]),
+                        None => {
+                            let e =
+                                ParseError::UnknownParameter(token.to_string(), key.to_string());
+                            return Some(Err(e));
+                        }
+                    }
+                }
+                None if i < params.len() => (i, part),
+                None => return Some(Err(ParseError::TooManyParameters(token.to_string()))),
+            };
+            match params[i].parse(token, value) {
+                Ok(value) => values[i] = Some(value),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        let values = match values.into_iter().collect::<Option<Vec<_>>>() {
+            Some(values) => values,
+            None => return Some(Err(ParseError::NotEnoughParameters(token.to_string()))),
+        };
+        let constructor = definition.constructor.clone();
+        let mut ctx = Context {
+            channels,
+            sample_rate,
+            token,
+            params,
+            values: &values,
+            seeds,
+            first_seed: None,
+        };
+        let op = constructor(&mut ctx);
+        let mut seeds = Seeds::new(ctx.first_seed.unwrap_or_default());
+        let (token, params) = (token.to_string(), params.clone());
+        Some(op.map(|op| match op {
+            Op::Connect(node, None) => {
+                let factory = move || {
+                    let mut ctx = Context {
+                        channels,
+                        sample_rate,
+                        token: &token,
+                        params: &params,
+                        values: &values,
+                        seeds: &mut seeds,
+                        first_seed: None,
+                    };
+                    match constructor(&mut ctx) {
+                        Ok(Op::Connect(node, _)) => node,
+                        _ => panic!("`{}` must create a node each time", token),
+                    }
+                };
+                Op::Connect(node, Some(Box::new(factory)))
+            }
+            op => op,
+        }))
+    }
+
+    /// Registry with all built-in modules.
+    pub fn builtin() -> Self {
+        let mut r = Registry::new();
+        fn1(&mut r, &["\\"], pure::recip);
+        fn1(&mut r, &["cheb2"], pure::cheb2);
+        fn1(&mut r, &["cheb3"], pure::cheb3);
+        fn1(&mut r, &["cheb4"], pure::cheb4);
+        fn1(&mut r, &["cheb5"], pure::cheb5);
+        fn1(&mut r, &["cheb6"], pure::cheb6);
+        fn1(&mut r, &["cos"], pure::cos);
+        fn1(&mut r, &["m2f", "midi2freq"], pure::midi2freq);
+        fn1(&mut r, &["round"], pure::round);
+        fn1(&mut r, &["sin"], pure::sin);
+        fn1(&mut r, &["unit"], pure::unit);
+        fn2(&mut r, &["*"], pure::mul);
+        fn2(&mut r, &["+"], pure::add);
+        fn2(&mut r, &["-"], pure::sub);
+        fn2(&mut r, &["/"], pure::div);
+        fn2(&mut r, &["^", "pow"], pure::pow);
+        fn2(&mut r, &["q", "quantize"], pure::quantize);
+        fn3(&mut r, &["r", "range"], pure::range);
+
+        r.register(&["dm", "dmetro"], &[], |ctx| {
+            node(DMetro::new(ctx.channels, ctx.sample_rate))
+        });
+        r.register(&["dmh", "dmetro_hold"], &[], |ctx| {
+            node(DMetroHold::new(ctx.channels, ctx.sample_rate))
+        });
+        r.register(&["h", "bqhpf"], &[], |ctx| {
+            node(BiQuad::new(
+                ctx.channels,
+                ctx.sample_rate,
+                make_hpf_coefficients,
+            ))
+        });
+        r.register(&["hpf"], &[], |ctx| {
+            node(HPF::new(ctx.channels, ctx.sample_rate))
+        });
+        r.register(&["impulse"], &[], |ctx| {
+            node(envelopes::Impulse::new(ctx.channels, ctx.sample_rate))
+        });
+        r.register(&["in", "input"], &[], |ctx| node(Input::new(ctx.channels)));
+        r.register(&["l", "bqlpf"], &[], |ctx| {
+            node(BiQuad::new(
+                ctx.channels,
+                ctx.sample_rate,
+                make_lpf_coefficients,
+            ))
+        });
+        r.register(&["lpf"], &[], |ctx| {
+            node(LPF::new(ctx.channels, ctx.sample_rate))
+        });
+        r.register(&["m", "metro"], &[], |ctx| {
+            node(Metro::new(ctx.channels, ctx.sample_rate))
+        });
+        r.register(&["mh", "metro_hold"], &[], |ctx| {
+            node(MetroHold::new(ctx.channels, ctx.sample_rate))
+        });
+        r.register(&["mix"], &[], |ctx| {
+            let channels = ctx.channels;
+            list_node(0, move |size| Mix::new(channels, size))
+        });
+        r.register(&["n", "noise"], &[], |ctx| {
+            let seed = ctx.seed();
+            node(WhiteNoise::new(ctx.channels, seed))
+        });
+        r.register(&["p", "pulse"], &[], |ctx| {
+            node(Pulse::new(ctx.channels, ctx.sample_rate))
+        });
+        r.register(&["pan1"], &[], |ctx| node(Pan1::new(ctx.channels)));
+        r.register(&["pan2"], &[], |ctx| node(Pan2::new(ctx.channels)));
+        r.register(&["s"], &[], |ctx| {
+            node(Osc::new(ctx.channels, ctx.sample_rate, pure::sine))
+        });
+        r.register(&["saw"], &[], |ctx| {
+            node(Phasor0::new(ctx.channels, ctx.sample_rate))
+        });
+        r.register(&["sh", "sample&hold"], &[], |ctx| {
+            node(SampleAndHold::new(ctx.channels))
+        });
+        r.register(
+            &["seq"],
+            &[Param::choice("mode", &["fwd", "rand", "pingpong"], Some(0))],
+            |ctx| {
+                let traversal = match ctx.int::<usize>("mode")? {
+                    1 => Traversal::Random,
+                    2 => Traversal::PingPong,
+                    _ => Traversal::Forward,
+                };
+                // Don't take seeds from random modules which follow when they are not needed.
+                let seed = match traversal {
+                    Traversal::Random => ctx.seed(),
+                    _ => 0,
+                };
+                let channels = ctx.channels;
+                list_node(1, move |size| Seq::new(channels, size, traversal, seed))
+            },
+        );
+        r.register(&["sine"], &[], |ctx| {
+            node(OscPhase::new(ctx.channels, ctx.sample_rate, pure::sine))
+        });
+        r.register(&["spectral_shuffle"], &[], |ctx| {
+            let mut rng = Box::new(Pcg64Mcg::seed_from_u64(ctx.seed()));
+            node(SpectralTransform::new(
+                ctx.channels,
+                2048,
+                64,
+                Box::new(move |freqs| freqs.shuffle(&mut rng)),
+            ))
+        });
+        r.register(&["splay"], &[], |ctx| {
+            let channels = ctx.channels;
+            list_node(0, move |size| Splay::new(channels, size))
+        });
+        r.register(&["t"], &[], |ctx| {
+            node(Osc::new(ctx.channels, ctx.sample_rate, pure::triangle))
+        });
+        r.register(&["tri"], &[], |ctx| {
+            node(OscPhase::new(ctx.channels, ctx.sample_rate, pure::triangle))
+        });
+        r.register(&["w"], &[], |ctx| {
+            node(Phasor::new(ctx.channels, ctx.sample_rate))
+        });
+        r.register(
+            &["yin"],
+            &[
+                Param::int("size", Some(1024)),
+                Param::int("period", Some(64)),
+                Param::float("threshold", Some(0.2)),
+            ],
+            |ctx| {
+                node(Yin::new(
+                    ctx.channels,
+                    ctx.sample_rate,
+                    ctx.int("size")?,
+                    ctx.int("period")?,
+                    ctx.float("threshold"),
+                ))
+            },
+        );
+        r.register(&["zip"], &[], |ctx| node(Zip::new(ctx.channels)));
+
+        r.register(&["delay"], &[Param::float("max", Some(60.0))], |ctx| {
+            node(Delay::new(ctx.channels, ctx.sample_rate, ctx.float("max")))
+        });
+        r.register(
+            &["fb", "feedback"],
+            &[Param::float("max", Some(60.0))],
+            |ctx| {
+                node(Feedback::new(
+                    ctx.channels,
+                    ctx.sample_rate,
+                    ctx.float("max"),
+                ))
+            },
+        );
+        r.register(&["conv"], &[Param::int("size", None)], |ctx| {
+            node(Convolution::new(ctx.channels, ctx.int("size")?))
+        });
+        r.register(&["convm"], &[Param::int("size", None)], |ctx| {
+            node(ConvolutionM::new(ctx.channels, ctx.int("size")?))
+        });
+        r.register(&["param"], &[Param::int("index", None)], |ctx| {
+            node(Parameter::new(ctx.channels, ctx.int("index")?))
+        });
+        r.register(&["chan"], &[Param::int("index", None)], |ctx| {
+            let index = ctx.int("index")?;
+            if index >= ctx.channels {
+                return Err(ParseError::ChannelOutOfRange {
+                    token: ctx.token.to_string(),
+                    channels: ctx.channels,
+                });
+            }
+            node(Channel::new(ctx.channels, index))
+        });
+        r
+    }
+}
+
+/// Op connecting the module, for use in constructors.
+pub fn node(module: impl Module + Send) -> Result<Op, ParseError> {
+    Ok(Op::Connect(Box::new(module), None))
+}
+
+/// Op connecting module which takes a list followed by `inputs` sources, for use in
+/// constructors. `module` creates the module for the list length.
+pub fn list_node<M, F>(inputs: u8, module: F) -> Result<Op, ParseError>
+where
+    M: Module + Send,
+    F: FnOnce(usize) -> M + 'static,
+{
+    Ok(Op::ConnectList {
+        inputs,
+        node: Box::new(move |size| Box::new(module(size)) as AudioNode),
+    })
+}
+
+fn fn1(r: &mut Registry, names: &[&str], f: fn(Sample) -> Sample) {
+    r.register(names, &[], move |ctx| node(Fn1::new(ctx.channels, f)));
+}
+
+fn fn2(r: &mut Registry, names: &[&str], f: fn(Sample, Sample) -> Sample) {
+    r.register(names, &[], move |ctx| node(Fn2::new(ctx.channels, f)));
+}
+
+fn fn3(r: &mut Registry, names: &[&str], f: fn(Sample, Sample, Sample) -> Sample) {
+    r.register(names, &[], move |ctx| node(Fn3::new(ctx.channels, f)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    /// Registry with `t` module, which parameters are stored into the returned cell when it's
+    /// constructed.
+    fn registry() -> (Registry, Rc<RefCell<Vec<f64>>>) {
+        let values = Rc::new(RefCell::new(Vec::new()));
+        let mut r = Registry::new();
+        let params = [
+            Param::int("n", None),
+            Param::float("x", Some(0.5)),
+            Param::choice("mode", &["up", "down"], Some(0)),
+        ];
+        let stored = values.clone();
+        r.register(&["t", "test"], &params, move |ctx| {
+            let values = vec![ctx.float("n"), ctx.float("x"), ctx.float("mode")];
+            *stored.borrow_mut() = values;
+            node(Constant::new(ctx.channels, 0.0))
+        });
+        (r, values)
+    }
+
+    /// Construct `token` and return its parameters' values.
+    fn construct(token: &str) -> Result<Vec<f64>, ParseError> {
+        let (r, values) = registry();
+        let op = r.construct(token, 1, 48_000, &mut Seeds::new(0));
+        op.expect("`t` is registered")?;
+        let values = values.borrow().clone();
+        Ok(values)
+    }
+
+    #[test]
+    fn positional_parameters() {
+        assert_eq!(construct("t:3").unwrap(), vec![3.0, 0.5, 0.0]);
+        assert_eq!(construct("t:3:2:down").unwrap(), vec![3.0, 2.0, 1.0]);
+        assert_eq!(construct("test:3:1/4").unwrap(), vec![3.0, 0.25, 0.0]);
+    }
+
+    #[test]
+    fn named_parameters() {
+        assert_eq!(construct("t:n=3").unwrap(), vec![3.0, 0.5, 0.0]);
+        assert_eq!(construct("t:mode=down:n=3").unwrap(), vec![3.0, 0.5, 1.0]);
+        assert_eq!(construct("t:3:x=2ms").unwrap(), vec![3.0, 0.002, 0.0]);
+        // Positional parameters are counted by their position in the token.
+        assert_eq!(construct("t:n=3:2").unwrap(), vec![3.0, 2.0, 0.0]);
+    }
+
+    #[test]
+    fn missing_parameters() {
+        assert!(matches!(
+            construct("t"),
+            Err(ParseError::NotEnoughParameters(_))
+        ));
+        assert!(matches!(
+            construct("t:x=2"),
+            Err(ParseError::NotEnoughParameters(_))
+        ));
+    }
+
+    #[test]
+    fn unknown_parameter() {
+        match construct("t:3:y=2") {
+            Err(ParseError::UnknownParameter(token, name)) => {
+                assert_eq!(token, "t:3:y=2");
+                assert_eq!(name, "y");
+            }
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn too_many_parameters() {
+        assert!(matches!(
+            construct("t:3:2:up:1"),
+            Err(ParseError::TooManyParameters(token)) if token == "t:3:2:up:1"
+        ));
+    }
+
+    #[test]
+    fn wrong_parameter_type() {
+        for token in &["t:3.5", "t:n=x", "t:3:x", "t:3:2:left", "t:3:mode=1"] {
+            match construct(token) {
+                Err(ParseError::WrongParameterType(t)) => assert_eq!(&t, token),
+                result => panic!("{}: unexpected result: {:?}", token, result),
+            }
+        }
+    }
+
+    #[test]
+    fn unregistered_name() {
+        let (r, _) = registry();
+        assert!(!r.contains("u"));
+        assert!(r.construct("u:3", 1, 48_000, &mut Seeds::new(0)).is_none());
+    }
+}
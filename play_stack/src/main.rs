@@ -1,4 +1,4 @@
-use audio_graph::{Module, Sample};
+use audio_graph::{AudioGraph, Module, Sample};
 use audio_stack::parse_graph;
 use cpal::traits::{DeviceTrait, EventLoopTrait, HostTrait};
 use std::io::Read;
@@ -17,14 +17,28 @@ fn main() {
         .default_output_format()
         .expect("Failed to get default output format");
 
-    let mut graph = parse_graph(&text, format.channels as _, format.sample_rate.0 as _, 0)
-        .expect("Failed to parse input");
+    let mut graph = match parse_graph(
+        &text,
+        format.channels as _,
+        format.sample_rate.0 as _,
+        0,
+        0,
+        None,
+    ) {
+        Ok(graph) => graph,
+        Err(err) => {
+            eprint!("{}", err.render(&text));
+            std::process::exit(1);
+        }
+    };
 
     let event_loop = host.event_loop();
     let stream_id = event_loop.build_output_stream(&device, &format).unwrap();
     event_loop.play_stream(stream_id.clone()).unwrap();
 
     let input = [];
+    let channels = format.channels as usize;
+    let mut output = Vec::new();
 
     event_loop.run(move |id, result| {
         let data = match result {
@@ -38,34 +52,40 @@ fn main() {
             cpal::StreamData::Output {
                 buffer: cpal::UnknownTypeOutputBuffer::U16(mut buffer),
             } => {
-                for frame in buffer.chunks_mut(format.channels as usize) {
-                    graph.sample(&input);
-                    for (out, sample) in frame.iter_mut().zip(graph.output()) {
-                        *out = ((sample * 0.5 + 0.5) * std::u16::MAX as Sample) as u16;
-                    }
+                render(&mut graph, &input, &mut output, buffer.len(), channels);
+                for (out, sample) in buffer.iter_mut().zip(&output) {
+                    *out = ((sample * 0.5 + 0.5) * Sample::from(u16::MAX)) as u16;
                 }
             }
             cpal::StreamData::Output {
                 buffer: cpal::UnknownTypeOutputBuffer::I16(mut buffer),
             } => {
-                for frame in buffer.chunks_mut(format.channels as usize) {
-                    graph.sample(&input);
-                    for (out, sample) in frame.iter_mut().zip(graph.output()) {
-                        *out = (sample * std::i16::MAX as Sample) as i16;
-                    }
+                render(&mut graph, &input, &mut output, buffer.len(), channels);
+                for (out, sample) in buffer.iter_mut().zip(&output) {
+                    *out = (sample * Sample::from(i16::MAX)) as i16;
                 }
             }
             cpal::StreamData::Output {
                 buffer: cpal::UnknownTypeOutputBuffer::F32(mut buffer),
             } => {
-                for frame in buffer.chunks_mut(format.channels as usize) {
-                    graph.sample(&input);
-                    for (out, sample) in frame.iter_mut().zip(graph.output()) {
-                        *out = *sample as f32;
-                    }
+                render(&mut graph, &input, &mut output, buffer.len(), channels);
+                for (out, sample) in buffer.iter_mut().zip(&output) {
+                    *out = *sample as f32;
                 }
             }
             _ => (),
         }
     });
 }
+
+/// Render `len` interleaved samples of `graph` into `output`, growing it when necessary.
+fn render(
+    graph: &mut AudioGraph,
+    input: &[Sample],
+    output: &mut Vec<Sample>,
+    len: usize,
+    channels: usize,
+) {
+    output.resize(len, 0.0);
+    graph.process(input, output, len / channels);
+}
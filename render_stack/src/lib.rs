@@ -0,0 +1,211 @@
+//! # render_stack
+//!
+//! Render audio graphs into WAV files offline, without an audio device, e.g. for bouncing or CI.
+use audio_graph::{AudioGraph, Module, Sample, MAX_FRAMES};
+use audio_stack::parse_graph;
+use hound::{SampleFormat, WavSpec, WavWriter};
+use std::fmt;
+use std::io::{Seek, Write};
+use std::path::Path;
+
+/// Sample format of the WAV file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Int16,
+    Int24,
+    Float32,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// Source doesn't parse or doesn't make a valid graph.
+    SourceError(audio_stack::Error),
+    WavError(hound::Error),
+    /// Graph outputs a different number of channels than requested.
+    ChannelsMismatch {
+        expected: u8,
+        actual: usize,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::SourceError(err) => write!(f, "{}", err),
+            Error::WavError(err) => write!(f, "failed to write WAV: {}", err),
+            Error::ChannelsMismatch { expected, actual } => write!(
+                f,
+                "graph outputs {} channels, but {} were requested",
+                actual, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<audio_stack::Error> for Error {
+    fn from(err: audio_stack::Error) -> Self {
+        Error::SourceError(err)
+    }
+}
+
+impl From<hound::Error> for Error {
+    fn from(err: hound::Error) -> Self {
+        Error::WavError(err)
+    }
+}
+
+/// Offline rendering settings.
+#[derive(Clone, Copy, Debug)]
+pub struct Renderer {
+    pub channels: u8,
+    pub sample_rate: u32,
+    /// Duration in seconds.
+    pub duration: f64,
+    pub format: Format,
+    /// Seed of random modules, ref `audio_stack::parse_ops`.
+    pub seed: u64,
+}
+
+impl Default for Renderer {
+    fn default() -> Self {
+        Renderer {
+            channels: 2,
+            sample_rate: 48_000,
+            duration: 10.0,
+            format: Format::Int16,
+            seed: 0,
+        }
+    }
+}
+
+impl Renderer {
+    /// How many frames fit `duration`.
+    pub fn frames(&self) -> usize {
+        (self.duration * Sample::from(self.sample_rate)).round() as _
+    }
+
+    /// Parse stack source and render it into the WAV file at `path`.
+    /// `source_path` is the file source is read from, if any, to resolve includes.
+    pub fn render_stack<P: AsRef<Path>>(
+        &self,
+        source: &str,
+        source_path: Option<&Path>,
+        path: P,
+    ) -> Result<(), Error> {
+        let mut graph = parse_graph(
+            source,
+            self.channels,
+            self.sample_rate,
+            0,
+            self.seed,
+            source_path,
+        )?;
+        self.render_graph(&mut graph, WavWriter::create(path, self.spec())?)
+    }
+
+    /// Render `graph` into the WAV `writer` and finalize it.
+    ///
+    /// Graph is expected to be built with the same channels count and sample rate as renderer
+    /// has. External inputs, if graph has any, are silent.
+    pub fn render_graph<W: Write + Seek>(
+        &self,
+        graph: &mut AudioGraph,
+        mut writer: WavWriter<W>,
+    ) -> Result<(), Error> {
+        let channels = graph.output().len();
+        if channels != self.channels as usize {
+            return Err(Error::ChannelsMismatch {
+                expected: self.channels,
+                actual: channels,
+            });
+        }
+        let input = vec![0.0; MAX_FRAMES * graph.inputs() as usize];
+        let mut output = vec![0.0; MAX_FRAMES * channels];
+        let mut frames = self.frames();
+        while frames > 0 {
+            let n = frames.min(MAX_FRAMES);
+            let output = &mut output[..n * channels];
+            graph.process(&input[..n * graph.inputs() as usize], output, n);
+            for &sample in output.iter() {
+                match self.format {
+                    Format::Int16 => writer.write_sample(quantize(sample, 16) as i16)?,
+                    Format::Int24 => writer.write_sample(quantize(sample, 24))?,
+                    Format::Float32 => writer.write_sample(sample as f32)?,
+                }
+            }
+            frames -= n;
+        }
+        writer.finalize()?;
+        Ok(())
+    }
+
+    /// WAV header matching renderer settings.
+    pub fn spec(&self) -> WavSpec {
+        let (bits_per_sample, sample_format) = match self.format {
+            Format::Int16 => (16, SampleFormat::Int),
+            Format::Int24 => (24, SampleFormat::Int),
+            Format::Float32 => (32, SampleFormat::Float),
+        };
+        WavSpec {
+            channels: u16::from(self.channels),
+            sample_rate: self.sample_rate,
+            bits_per_sample,
+            sample_format,
+        }
+    }
+}
+
+/// Convert sample to the integer of `bits` size, clipping it to -1..1 first.
+fn quantize(sample: Sample, bits: u32) -> i32 {
+    let max = ((1 << (bits - 1)) - 1) as Sample;
+    (sample.clamp(-1.0, 1.0) * max).round() as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quantize_int16() {
+        assert_eq!(quantize(0.0, 16), 0);
+        assert_eq!(quantize(1.0, 16), 32767);
+        assert_eq!(quantize(-1.0, 16), -32767);
+        assert_eq!(quantize(0.5, 16), 16384);
+        assert_eq!(quantize(1.5, 16), 32767);
+        assert_eq!(quantize(-1.5, 16), -32767);
+    }
+
+    #[test]
+    fn quantize_int24() {
+        assert_eq!(quantize(1.0, 24), 8_388_607);
+        assert_eq!(quantize(-1.0, 24), -8_388_607);
+        assert_eq!(quantize(Sample::INFINITY, 24), 8_388_607);
+        assert_eq!(quantize(Sample::NEG_INFINITY, 24), -8_388_607);
+        assert_eq!(quantize(1.0 / 8_388_607.0, 24), 1);
+    }
+
+    #[test]
+    fn float32_is_not_clipped() {
+        let renderer = Renderer {
+            channels: 1,
+            duration: 0.001,
+            format: Format::Float32,
+            ..Renderer::default()
+        };
+        let mut graph = parse_graph("1.5", 1, renderer.sample_rate, 0, 0, None).unwrap();
+        let mut wav = std::io::Cursor::new(Vec::new());
+        let spec = renderer.spec();
+        renderer
+            .render_graph(&mut graph, WavWriter::new(&mut wav, spec).unwrap())
+            .unwrap();
+        wav.set_position(0);
+        let samples = hound::WavReader::new(wav)
+            .unwrap()
+            .into_samples::<f32>()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(samples, vec![1.5; 48]);
+    }
+}
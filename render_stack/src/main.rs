@@ -0,0 +1,73 @@
+use render_stack::{Error, Format, Renderer};
+use std::io::Read;
+use std::path::Path;
+
+const USAGE: &str = "Usage: render [options] <output.wav> [source.sg]
+
+Render stack source (from the file or stdin) into a WAV file.
+
+Options:
+    -c <channels>     number of channels (default 2)
+    -r <rate>         sample rate (default 48000)
+    -d <seconds>      duration (default 10)
+    -f <16|24|32f>    sample format (default 16)
+    -s <seed>         seed of random modules (default 0)";
+
+fn main() {
+    let mut renderer = Renderer::default();
+    let mut paths = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| usage());
+        match arg.as_str() {
+            "-c" => renderer.channels = value().parse().unwrap_or_else(|_| usage()),
+            "-r" => renderer.sample_rate = value().parse().unwrap_or_else(|_| usage()),
+            "-d" => renderer.duration = value().parse().unwrap_or_else(|_| usage()),
+            "-s" => renderer.seed = value().parse().unwrap_or_else(|_| usage()),
+            "-f" => {
+                renderer.format = match value().as_str() {
+                    "16" => Format::Int16,
+                    "24" => Format::Int24,
+                    "32f" => Format::Float32,
+                    _ => usage(),
+                }
+            }
+            "-h" | "--help" => usage(),
+            _ => paths.push(arg),
+        }
+    }
+
+    let (output, source) = match paths.as_slice() {
+        [output] => (output, None),
+        [output, source] => (output, Some(source)),
+        _ => usage(),
+    };
+
+    let text = match source {
+        Some(path) => std::fs::read_to_string(path).expect("Failed to read source"),
+        None => {
+            let mut text = String::new();
+            std::io::stdin()
+                .read_to_string(&mut text)
+                .expect("Failed to read stdin");
+            text
+        }
+    };
+
+    match renderer.render_stack(&text, source.map(Path::new), output) {
+        Ok(()) => {}
+        Err(Error::SourceError(err)) => {
+            eprint!("{}", err.render(&text));
+            std::process::exit(1);
+        }
+        Err(err) => {
+            eprintln!("error: {}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    std::process::exit(2);
+}
@@ -0,0 +1,59 @@
+//! Render patches into WAV files and check what is written.
+use hound::{SampleFormat, WavReader};
+use render_stack::{Error, Format, Renderer};
+use std::path::PathBuf;
+
+/// Path of a WAV file in the temporary directory, unique for the test `name`.
+fn temp_wav(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("render_stack-{}-{}.wav", std::process::id(), name))
+}
+
+#[test]
+fn wav_header_and_frames() {
+    let path = temp_wav("header");
+    let renderer = Renderer {
+        channels: 2,
+        sample_rate: 44_100,
+        duration: 0.1,
+        format: Format::Int24,
+        seed: 0,
+    };
+    renderer.render_stack("0.5", None, &path).unwrap();
+    let reader = WavReader::open(&path).unwrap();
+    let spec = reader.spec();
+    assert_eq!(spec.channels, 2);
+    assert_eq!(spec.sample_rate, 44_100);
+    assert_eq!(spec.bits_per_sample, 24);
+    assert_eq!(spec.sample_format, SampleFormat::Int);
+    assert_eq!(reader.duration() as usize, renderer.frames());
+    assert_eq!(renderer.frames(), 4410);
+    let samples = reader
+        .into_samples::<i32>()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(samples, vec![4_194_304; 2 * 4410]);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn frames_are_not_rounded_to_blocks() {
+    let path = temp_wav("frames");
+    let renderer = Renderer {
+        channels: 1,
+        duration: 1001.0 / 48_000.0,
+        ..Renderer::default()
+    };
+    renderer.render_stack("0.5", None, &path).unwrap();
+    let reader = WavReader::open(&path).unwrap();
+    assert_eq!(reader.spec().bits_per_sample, 16);
+    assert_eq!(reader.duration(), 1001);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn source_error() {
+    let path = temp_wav("error");
+    let result = Renderer::default().render_stack("0.5 foo", None, &path);
+    assert!(matches!(result, Err(Error::SourceError(_))));
+    assert!(!path.exists());
+}
@@ -0,0 +1,86 @@
+use audio_stack::parse_ops;
+use std::io::Read;
+use std::path::Path;
+
+const USAGE: &str = "Usage: sg <command> [options]
+
+Commands:
+    check [source.sg]   check stack source (from the file or stdin) without playing it
+
+Options of check:
+    -c <channels>       number of channels (default 2)
+    -r <rate>           sample rate (default 48000)
+
+Check prints errors, warnings, notes on connections delayed to break cycles and how many items
+are left on the stack, only the top one is played. It exits with 1 if there are errors or
+warnings.";
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    match args.next().as_deref() {
+        Some("check") => check(args),
+        _ => usage(),
+    }
+}
+
+fn check(mut args: impl Iterator<Item = String>) {
+    let (mut channels, mut sample_rate) = (2, 48_000);
+    let mut source = None;
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| usage());
+        match arg.as_str() {
+            "-c" => channels = value().parse().unwrap_or_else(|_| usage()),
+            "-r" => sample_rate = value().parse().unwrap_or_else(|_| usage()),
+            "-h" | "--help" => usage(),
+            _ if source.is_none() => source = Some(arg),
+            _ => usage(),
+        }
+    }
+
+    let text = match &source {
+        Some(path) => std::fs::read_to_string(path).expect("Failed to read source"),
+        None => {
+            let mut text = String::new();
+            std::io::stdin()
+                .read_to_string(&mut text)
+                .expect("Failed to read stdin");
+            text
+        }
+    };
+
+    let program = parse_ops(
+        &text,
+        channels,
+        sample_rate,
+        0,
+        source.as_deref().map(Path::new),
+    );
+    let checked = program.and_then(|program| {
+        let (warnings, outputs) = program.check()?;
+        let (_, delayed) = program.build_with_delayed_edges(channels, 0)?;
+        Ok((warnings, delayed, outputs))
+    });
+    match checked {
+        Ok((warnings, delayed, outputs)) => {
+            for warning in &warnings {
+                eprint!("{}", warning.render(&text));
+            }
+            for note in &delayed {
+                eprint!("{}", note.render(&text));
+            }
+            println!("{} item(s) left on the stack", outputs);
+            if !warnings.is_empty() {
+                std::process::exit(1);
+            }
+        }
+        Err(err) => {
+            eprint!("{}", err.render(&text));
+            std::process::exit(1);
+        }
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    std::process::exit(2);
+}
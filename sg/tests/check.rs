@@ -0,0 +1,72 @@
+//! Run `sg check` on sources from stdin and check its output and exit status.
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+fn sg(args: &[&str], source: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_sg"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(source.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn check(source: &str) -> (Option<i32>, String, String) {
+    let output = sg(&["check", "-c", "1"], source);
+    (
+        output.status.code(),
+        String::from_utf8(output.stdout).unwrap(),
+        String::from_utf8(output.stderr).unwrap(),
+    )
+}
+
+#[test]
+fn clean_source() {
+    let (code, stdout, stderr) = check("440 s 0.5 *");
+    assert_eq!(code, Some(0));
+    assert_eq!(stdout, "1 item(s) left on the stack\n");
+    assert_eq!(stderr, "");
+}
+
+#[test]
+fn discarded_items() {
+    let (code, stdout, stderr) = check("440 s 220 s");
+    assert_eq!(code, Some(1));
+    assert_eq!(stdout, "2 item(s) left on the stack\n");
+    assert!(stderr.starts_with("warning: item is left on the stack and discarded\n --> 1:5\n"));
+    assert_eq!(stderr.matches("warning").count(), 1);
+}
+
+#[test]
+fn unused_node() {
+    let (code, stdout, stderr) = check("440 s pop\n1");
+    assert_eq!(code, Some(1));
+    assert_eq!(stdout, "1 item(s) left on the stack\n");
+    assert!(stderr.starts_with("warning: output of the node is never used\n --> 1:5\n"));
+}
+
+#[test]
+fn error() {
+    let (code, stdout, stderr) = check("1 +");
+    assert_eq!(code, Some(1));
+    assert_eq!(stdout, "");
+    assert!(stderr.starts_with("error: "), "{}", stderr);
+    assert!(stderr.contains(" --> 1:3\n"), "{}", stderr);
+}
+
+#[test]
+fn usage() {
+    let output = sg(&["play"], "");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .starts_with("Usage: sg"));
+}
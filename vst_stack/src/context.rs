@@ -2,5 +2,7 @@ pub struct Context {
     pub channels: u8,
     pub sample_rate: u32,
     pub parameters: u8,
+    /// Seed of random modules, ref `audio_stack::parse_ops`.
+    pub seed: u64,
 }
 
@@ -3,30 +3,44 @@ extern crate objc;
 
 mod context;
 mod editor;
+mod report;
 
-use audio_graph::{AudioGraph, Module, Sample};
-use audio_stack::parse_graph;
+use audio_graph::{
+    handoff, AudioGraph, GraphReceiver, GraphSwapper, Layout, Module, Sample, MAX_FRAMES,
+};
+use audio_stack::parse_ops;
 use chrono::prelude::*;
 use context::Context;
 use editor::Editor;
 use notify::{watcher, DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
 use parking_lot::Mutex;
 use rand::random;
-use std::sync::mpsc::channel;
+use report::Report;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, RecvTimeoutError};
 use std::sync::Arc;
 use std::time::Duration;
 use vst::plugin::{Info, Plugin};
 
 const CHANNELS: u8 = 2;
 const PARAMETERS: u8 = 16;
+const INPUTS: u8 = CHANNELS + PARAMETERS;
+/// Crossfade duration between the old and the new graph on reload, in seconds.
+const FADE_TIME: f64 = 0.05;
+/// How often graphs retired by the audio thread are deallocated.
+const COLLECT_PERIOD: Duration = Duration::from_millis(100);
 
 struct SoundGarden {
     context: Arc<Mutex<Context>>,
     editor: Editor,
-    graph: Arc<Mutex<AudioGraph>>,
+    graph: GraphSwapper,
+    receiver: GraphReceiver,
+    /// Interleaved graph input and output, for at most `MAX_FRAMES` frames.
     input: Vec<Sample>,
+    output: Vec<Sample>,
     parameters: Vec<f64>,
-    _watcher: RecommendedWatcher,
+    _watcher: Arc<Mutex<RecommendedWatcher>>,
 }
 
 impl Default for SoundGarden {
@@ -47,8 +61,13 @@ impl Default for SoundGarden {
             channels: CHANNELS,
             sample_rate: 48_000,
             parameters: PARAMETERS,
+            seed: 0,
         }));
-        let graph = Arc::new(Mutex::new(AudioGraph::new(CHANNELS, CHANNELS + PARAMETERS)));
+        let graph = GraphSwapper::new(
+            AudioGraph::new(CHANNELS, INPUTS),
+            (FADE_TIME * 48_000.0) as _,
+        );
+        let (sender, receiver) = handoff(4);
         let editor = Editor {
             path: source_path.clone(),
             is_open: false,
@@ -63,39 +82,104 @@ impl Default for SoundGarden {
         // Add a path to be watched. All files and directories at that path and
         // below will be monitored for changes.
         watcher
-            .watch(source_dir, RecursiveMode::NonRecursive)
+            .watch(&source_dir, RecursiveMode::NonRecursive)
             .unwrap();
 
-        let new_graph = graph.clone();
+        let watcher = Arc::new(Mutex::new(watcher));
+        // Watcher is owned by the plugin, so that the thread stops when it's dropped.
+        let weak_watcher = Arc::downgrade(&watcher);
         let ctx = context.clone();
+        let report = Report::new(&source_path);
         std::thread::spawn(move || {
             let mut source_code = "".to_string();
+            // Layout of the last graph sent to the audio thread, to plan migration from it.
+            let mut layout = Layout::default();
+            // Canonical paths of included files, source is reloaded when any of them changes.
+            let mut includes = Vec::<PathBuf>::new();
+            let mut watched_dirs = HashSet::new();
+            watched_dirs.extend(Path::new(&source_dir).canonicalize().ok());
             loop {
-                match rx.recv() {
+                match rx.recv_timeout(COLLECT_PERIOD) {
                     Ok(DebouncedEvent::Create(path)) | Ok(DebouncedEvent::Write(path)) => {
-                        if !path.to_str().unwrap().contains(&hash) {
+                        // Other files, including reports, are not interesting.
+                        let path = path.canonicalize().ok();
+                        let is_source = path == Path::new(&source_path).canonicalize().ok();
+                        let is_include = path.is_some_and(|path| includes.contains(&path));
+                        if !is_source && !is_include {
                             continue;
                         }
-                        if let Ok(code) = std::fs::read_to_string(path) {
-                            if source_code == code {
+                        if let Ok(code) = std::fs::read_to_string(&source_path) {
+                            // Included files might change even when source doesn't.
+                            if is_source && source_code == code {
                                 continue;
                             }
                             source_code = code;
                             let ctx = ctx.lock();
-                            // TODO error reporting
-                            if let Ok(graph) = parse_graph(
+                            let result = match parse_ops(
                                 &source_code,
                                 ctx.channels,
                                 ctx.sample_rate,
-                                ctx.channels + ctx.parameters,
+                                ctx.seed,
+                                Some(Path::new(&source_path)),
                             ) {
-                                *new_graph.lock() = graph;
+                                Ok(program) => {
+                                    includes = program.includes.clone();
+                                    let inputs = ctx.channels + ctx.parameters;
+                                    program.build_with_delayed_edges(ctx.channels, inputs)
+                                }
+                                Err(err) => {
+                                    // Reload when the file with error is fixed.
+                                    for path in err.included() {
+                                        if !includes.iter().any(|include| include == path) {
+                                            includes.push(path.to_path_buf());
+                                        }
+                                    }
+                                    Err(err)
+                                }
+                            };
+                            if let Some(watcher) = weak_watcher.upgrade() {
+                                let mut watcher = watcher.lock();
+                                for dir in includes.iter().filter_map(|path| path.parent()) {
+                                    if watched_dirs.insert(dir.to_path_buf()) {
+                                        let _ = watcher.watch(dir, RecursiveMode::NonRecursive);
+                                    }
+                                }
+                            }
+                            match result {
+                                Ok((mut graph, delayed)) => {
+                                    // Match nodes here to let the audio thread only copy their
+                                    // phases, delay lines etc.
+                                    graph.plan_migration(&layout);
+                                    let new_layout = graph.layout();
+                                    match sender.send(graph) {
+                                        Ok(()) => {
+                                            layout = new_layout;
+                                            let notes = delayed
+                                                .iter()
+                                                .map(|note| note.render(&source_code))
+                                                .collect::<String>();
+                                            report.success(&notes);
+                                        }
+                                        Err(_) => {
+                                            // Forget the source to not skip it on the next save.
+                                            source_code.clear();
+                                            report.failure(
+                                                "audio thread is busy",
+                                                "Audio thread is busy, save again.\n",
+                                            );
+                                        }
+                                    }
+                                }
+                                Err(err) => {
+                                    report.failure(&err.to_string(), &err.render(&source_code))
+                                }
                             }
                         }
                     }
-                    Err(_) => break,
-                    _ => continue,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                    _ => {}
                 }
+                sender.collect();
             }
             // let _ = std::fs::remove_file(source_path);
         });
@@ -104,7 +188,9 @@ impl Default for SoundGarden {
             context,
             editor,
             graph,
-            input: vec![0.0; (CHANNELS + PARAMETERS) as _],
+            receiver,
+            input: vec![0.0; MAX_FRAMES * INPUTS as usize],
+            output: vec![0.0; MAX_FRAMES * CHANNELS as usize],
             parameters: vec![0.0; PARAMETERS as _],
             _watcher: watcher,
         }
@@ -132,7 +218,11 @@ impl Plugin for SoundGarden {
     }
 
     fn set_sample_rate(&mut self, rate: f32) {
+        // New graphs are parsed with the new rate and the playing one is adapted to it.
         self.context.lock().sample_rate = rate as _;
+        self.graph.set_sample_rate(rate as _);
+        self.graph
+            .set_fade_frames((FADE_TIME * Sample::from(rate)) as _);
     }
 
     fn can_be_automated(&self, _index: i32) -> bool {
@@ -155,55 +245,106 @@ impl Plugin for SoundGarden {
 
     // #[no_alloc]
     fn process(&mut self, buffer: &mut vst::buffer::AudioBuffer<f32>) {
-        let (inputs, mut outputs) = buffer.split();
-
-        // Iterate over inputs as (&f32, &f32)
-        let (left, right) = inputs.split_at(1);
-        let stereo_in = left[0].iter().zip(right[0].iter());
-
-        // Iterate over outputs as (&mut f32, &mut f32)
-        let (mut left, mut right) = outputs.split_at_mut(1);
-        let stereo_out = left[0].iter_mut().zip(right[0].iter_mut());
-
-        // Prepare parameters and graph
-        self.input[CHANNELS as _..].clone_from_slice(&self.parameters);
-        let mut g = self.graph.lock();
-
-        // Zip and process
-        for ((left_in, right_in), (left_out, right_out)) in stereo_in.zip(stereo_out) {
-            self.input[0] = Sample::from(*left_in);
-            self.input[1] = Sample::from(*right_in);
-            g.sample(&self.input);
-            let output = g.output();
-            *left_out = output[0] as f32;
-            *right_out = output[1] as f32;
+        let frames = buffer.samples();
+        let (inputs, outputs) = buffer.split();
+        let (left_in, right_in) = (inputs.get(0), inputs.get(1));
+        let (left_out, right_out) = (outputs.get_mut(0), outputs.get_mut(1));
+
+        self.receive();
+
+        // Buffers are allocated for MAX_FRAMES, so longer blocks are processed in chunks.
+        for start in (0..frames).step_by(MAX_FRAMES) {
+            let range = start..frames.min(start + MAX_FRAMES);
+            let n = range.len();
+
+            // Interleave inputs and parameters into graph input frames
+            for (frame, (left, right)) in self
+                .input
+                .chunks_mut(INPUTS as _)
+                .zip(left_in[range.clone()].iter().zip(&right_in[range.clone()]))
+            {
+                frame[0] = Sample::from(*left);
+                frame[1] = Sample::from(*right);
+                frame[CHANNELS as _..].copy_from_slice(&self.parameters);
+            }
+
+            let output = &mut self.output[..n * CHANNELS as usize];
+            self.graph
+                .process(&self.input[..n * INPUTS as usize], output, n);
+
+            // De-interleave graph output frames into outputs
+            for (frame, (left, right)) in output.chunks(CHANNELS as _).zip(
+                left_out[range.clone()]
+                    .iter_mut()
+                    .zip(right_out[range].iter_mut()),
+            ) {
+                *left = frame[0] as f32;
+                *right = frame[1] as f32;
+            }
         }
+        self.retire();
     }
 
     // #[no_alloc]
     fn process_f64(&mut self, buffer: &mut vst::buffer::AudioBuffer<f64>) {
-        let (inputs, mut outputs) = buffer.split();
-
-        // Iterate over inputs as (&f32, &f32)
-        let (left, right) = inputs.split_at(1);
-        let stereo_in = left[0].iter().zip(right[0].iter());
-
-        // Iterate over outputs as (&mut f32, &mut f32)
-        let (mut left, mut right) = outputs.split_at_mut(1);
-        let stereo_out = left[0].iter_mut().zip(right[0].iter_mut());
-
-        // Prepare parameters and graph
-        self.input[CHANNELS as _..].clone_from_slice(&self.parameters);
-        let mut g = self.graph.lock();
-
-        // Zip and process
-        for ((left_in, right_in), (left_out, right_out)) in stereo_in.zip(stereo_out) {
-            self.input[0] = *left_in;
-            self.input[1] = *right_in;
-            g.sample(&self.input);
-            let output = g.output();
-            *left_out = output[0];
-            *right_out = output[1];
+        let frames = buffer.samples();
+        let (inputs, outputs) = buffer.split();
+        let (left_in, right_in) = (inputs.get(0), inputs.get(1));
+        let (left_out, right_out) = (outputs.get_mut(0), outputs.get_mut(1));
+
+        self.receive();
+
+        // Buffers are allocated for MAX_FRAMES, so longer blocks are processed in chunks.
+        for start in (0..frames).step_by(MAX_FRAMES) {
+            let range = start..frames.min(start + MAX_FRAMES);
+            let n = range.len();
+
+            // Interleave inputs and parameters into graph input frames
+            for (frame, (left, right)) in self
+                .input
+                .chunks_mut(INPUTS as _)
+                .zip(left_in[range.clone()].iter().zip(&right_in[range.clone()]))
+            {
+                frame[0] = *left;
+                frame[1] = *right;
+                frame[CHANNELS as _..].copy_from_slice(&self.parameters);
+            }
+
+            let output = &mut self.output[..n * CHANNELS as usize];
+            self.graph
+                .process(&self.input[..n * INPUTS as usize], output, n);
+
+            // De-interleave graph output frames into outputs
+            for (frame, (left, right)) in output.chunks(CHANNELS as _).zip(
+                left_out[range.clone()]
+                    .iter_mut()
+                    .zip(right_out[range].iter_mut()),
+            ) {
+                *left = frame[0];
+                *right = frame[1];
+            }
+        }
+        self.retire();
+    }
+}
+
+impl SoundGarden {
+    /// Swap to the latest graph sent by the watcher thread, if any.
+    fn receive(&mut self) {
+        if let Some(graph) = self.receiver.receive() {
+            // Migration is planned by the watcher thread, so it doesn't allocate, and the
+            // swapper spreads copying of large state over several blocks.
+            // Make room for graphs the swap retires.
+            self.retire();
+            self.graph.swap(graph);
+            self.retire();
+        }
+    }
+
+    /// Send graphs which are done playing to the watcher thread for deallocation.
+    fn retire(&mut self) {
+        while let Some(graph) = self.graph.retired() {
+            self.receiver.retire(graph);
         }
     }
 }
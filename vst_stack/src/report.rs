@@ -0,0 +1,61 @@
+use chrono::prelude::*;
+use std::fs::OpenOptions;
+use std::io::Write;
+
+/// Reports results of source reloads next to the source file, so editor integrations can show
+/// them:
+/// - `<source>.err` contains diagnostics of the last reload, it's empty if it succeeded without
+///   notes;
+/// - `<source>.log` accumulates timestamped results of all reloads.
+pub struct Report {
+    err_path: String,
+    log_path: String,
+}
+
+impl Report {
+    pub fn new(source_path: &str) -> Self {
+        Report {
+            err_path: format!("{}.err", source_path),
+            log_path: format!("{}.log", source_path),
+        }
+    }
+
+    /// `notes` are diagnostics which don't prevent reload, e.g. connections delayed to break
+    /// cycles.
+    pub fn success(&self, notes: &str) {
+        eprint!("{}", notes);
+        self.write_err(notes);
+        if notes.is_empty() {
+            self.log("reloaded");
+        } else {
+            self.log("reloaded with notes");
+        }
+    }
+
+    /// `message` is a one-line summary for the log, `diagnostics` is a detailed description.
+    pub fn failure(&self, message: &str, diagnostics: &str) {
+        eprint!("{}", diagnostics);
+        self.write_err(diagnostics);
+        self.log(&format!("failed: {}", message));
+    }
+
+    fn write_err(&self, text: &str) {
+        if let Err(err) = std::fs::write(&self.err_path, text) {
+            eprintln!("Failed to write {}: {}", self.err_path, err);
+        }
+    }
+
+    fn log(&self, message: &str) {
+        let result = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.log_path)
+            .and_then(|mut file| {
+                let dt = Local::now();
+                writeln!(file, "{} {}", dt.format("%Y-%m-%d %H:%M:%S"), message)
+            });
+        if let Err(err) = result {
+            eprintln!("Failed to write {}: {}", self.log_path, err);
+        }
+    }
+}